@@ -0,0 +1,23 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    token TEXT NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_token TEXT NOT NULL,
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+-- Users get back their most recent token, or a fresh random one
+INSERT INTO users_old(id, username, token, auth_provider, ext_token)
+    SELECT id, username,
+        COALESCE(
+            (SELECT token FROM sessions WHERE sessions.user_id = users.id ORDER BY id DESC LIMIT 1),
+            LOWER(HEX(RANDOMBLOB(15)))
+        ),
+        auth_provider, ext_token
+    FROM users;
+
+DROP TABLE sessions;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
@@ -0,0 +1,33 @@
+-- Sessions of the users, one per login
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    user_agent TEXT,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX sessions_token ON sessions(token);
+
+-- Existing tokens become sessions, so that nobody gets logged out
+INSERT INTO sessions(user_id, token, created_at, last_seen, expires_at)
+    SELECT id, token, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, DATETIME('now', '+30 days')
+    FROM users;
+
+-- The token is not stored on the user anymore
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_token TEXT NOT NULL,
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO users_new(id, username, auth_provider, ext_token)
+    SELECT id, username, auth_provider, ext_token FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+-- Hashed tokens can't be turned back into plain ones, so sessions are dropped
+DROP TABLE legacy_tokens;
+DROP TABLE sessions;
+
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    user_agent TEXT,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX sessions_token ON sessions(token);
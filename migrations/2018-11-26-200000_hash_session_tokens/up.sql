@@ -0,0 +1,32 @@
+-- Tokens are now stored as a lookup prefix and a keyed hash.
+ALTER TABLE sessions RENAME TO sessions_plain;
+
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    token_prefix VARCHAR(8) NOT NULL,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    user_agent TEXT,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX sessions_token_prefix ON sessions(token_prefix);
+
+-- The key of the hash lives in the configuration, so existing tokens are kept aside and
+-- hashed by the server when it starts. Until then, their empty hash matches no token.
+INSERT INTO sessions(id, user_id, token_prefix, token_hash, created_at, last_seen, expires_at, user_agent)
+    SELECT id, user_id, SUBSTR(token, 1, 8), '', created_at, last_seen, expires_at, user_agent
+    FROM sessions_plain;
+
+CREATE TABLE legacy_tokens (
+    session_id INTEGER PRIMARY KEY NOT NULL,
+    token TEXT NOT NULL,
+    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
+
+INSERT INTO legacy_tokens(session_id, token) SELECT id, token FROM sessions_plain;
+
+DROP TABLE sessions_plain;
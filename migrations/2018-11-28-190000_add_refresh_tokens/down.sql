@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_token TEXT NOT NULL,
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO users_old(id, username, auth_provider, ext_token)
+    SELECT id, username, auth_provider, ext_token FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
@@ -0,0 +1,3 @@
+-- Providers such as Gitlab give short-lived access tokens, along with a refresh token
+ALTER TABLE users ADD COLUMN ext_refresh_token TEXT;
+ALTER TABLE users ADD COLUMN ext_token_expires_at TIMESTAMP;
@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_provider_ext_id;
+
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_token TEXT NOT NULL,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO users_old(id, username, auth_provider, ext_token, ext_refresh_token, ext_token_expires_at)
+    SELECT id, username, auth_provider, ext_token, ext_refresh_token, ext_token_expires_at
+    FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
@@ -0,0 +1,5 @@
+-- Users are identified by the immutable ID given by their provider rather than by their
+-- username, which can change. Existing users get theirs on their next login
+ALTER TABLE users ADD COLUMN ext_id TEXT;
+
+CREATE UNIQUE INDEX users_provider_ext_id ON users(auth_provider, ext_id);
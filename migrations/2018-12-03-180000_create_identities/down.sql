@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+-- Users only keep their oldest identity
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_token TEXT NOT NULL,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    ext_id TEXT,
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO users_old(id, username, auth_provider, ext_token, ext_refresh_token,
+        ext_token_expires_at, ext_id)
+    SELECT users.id, users.username, identities.auth_provider, identities.ext_token,
+        identities.ext_refresh_token, identities.ext_token_expires_at, identities.ext_id
+    FROM users
+    INNER JOIN identities ON identities.id = (
+        SELECT MIN(id) FROM identities WHERE identities.user_id = users.id
+    );
+
+DROP TABLE identities;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_provider_ext_id ON users(auth_provider, ext_id);
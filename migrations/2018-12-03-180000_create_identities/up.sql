@@ -0,0 +1,37 @@
+-- Identities of the users on the authentication providers.
+-- A user can link several of them, and log in with any
+CREATE TABLE identities (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_id TEXT,
+    username VARCHAR(30) NOT NULL,
+    ext_token TEXT NOT NULL,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO identities(user_id, auth_provider, ext_id, username, ext_token, ext_refresh_token,
+        ext_token_expires_at, created_at)
+    SELECT id, auth_provider, ext_id, username, ext_token, ext_refresh_token,
+        ext_token_expires_at, CURRENT_TIMESTAMP
+    FROM users;
+
+CREATE UNIQUE INDEX identities_provider_ext_id ON identities(auth_provider, ext_id);
+CREATE UNIQUE INDEX identities_user_provider ON identities(user_id, auth_provider);
+
+-- Users only keep what doesn't depend on the provider
+DROP INDEX users_provider_ext_id;
+
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL
+);
+
+INSERT INTO users_new(id, username) SELECT id, username FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE local_credentials;
+
+-- Identities without external token, such as local ones, can't be kept
+CREATE TABLE identities_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_id TEXT,
+    username VARCHAR(30) NOT NULL,
+    ext_token TEXT NOT NULL,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO identities_old SELECT * FROM identities WHERE ext_token IS NOT NULL;
+
+DROP TABLE identities;
+ALTER TABLE identities_old RENAME TO identities;
+
+CREATE UNIQUE INDEX identities_provider_ext_id ON identities(auth_provider, ext_id);
+CREATE UNIQUE INDEX identities_user_provider ON identities(user_id, auth_provider);
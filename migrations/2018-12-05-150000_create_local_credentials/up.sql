@@ -0,0 +1,31 @@
+-- Identities of local accounts have no external token
+CREATE TABLE identities_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_id TEXT,
+    username VARCHAR(30) NOT NULL,
+    ext_token TEXT,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO identities_new SELECT * FROM identities;
+
+DROP TABLE identities;
+ALTER TABLE identities_new RENAME TO identities;
+
+CREATE UNIQUE INDEX identities_provider_ext_id ON identities(auth_provider, ext_id);
+CREATE UNIQUE INDEX identities_user_provider ON identities(user_id, auth_provider);
+
+-- Passwords of the local accounts, for deployments that can't reach any OAUTH provider
+CREATE TABLE local_credentials (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    identity_id INTEGER NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(identity_id) REFERENCES identities(id)
+);
@@ -0,0 +1,11 @@
+-- Pending passwordless logins, each one sent by email as a single-use link
+CREATE TABLE email_logins (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL,
+    token_prefix VARCHAR(8) NOT NULL,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX email_logins_token_prefix ON email_logins(token_prefix);
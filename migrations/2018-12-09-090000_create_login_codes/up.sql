@@ -0,0 +1,12 @@
+-- Short-lived, single-use codes handed to the frontend after a login,
+-- which it trades for the token of a new session
+CREATE TABLE login_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    code_prefix VARCHAR(8) NOT NULL,
+    code_hash TEXT NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX login_codes_code_prefix ON login_codes(code_prefix);
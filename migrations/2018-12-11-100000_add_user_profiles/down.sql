@@ -0,0 +1,36 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE identities_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    auth_provider INTEGER NOT NULL,
+    ext_id TEXT,
+    username VARCHAR(30) NOT NULL,
+    ext_token TEXT,
+    ext_refresh_token TEXT,
+    ext_token_expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(auth_provider) REFERENCES authprovider(id)
+);
+
+INSERT INTO identities_old(id, user_id, auth_provider, ext_id, username, ext_token,
+        ext_refresh_token, ext_token_expires_at, created_at)
+    SELECT id, user_id, auth_provider, ext_id, username, ext_token, ext_refresh_token,
+        ext_token_expires_at, created_at
+    FROM identities;
+
+DROP TABLE identities;
+ALTER TABLE identities_old RENAME TO identities;
+
+CREATE UNIQUE INDEX identities_provider_ext_id ON identities(auth_provider, ext_id);
+CREATE UNIQUE INDEX identities_user_provider ON identities(user_id, auth_provider);
+
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL
+);
+
+INSERT INTO users_old(id, username) SELECT id, username FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
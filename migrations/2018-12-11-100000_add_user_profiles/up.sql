@@ -0,0 +1,25 @@
+-- Users get a profile they can edit, and keep track of when they joined
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    display_name VARCHAR(30),
+    anonymous BOOLEAN NOT NULL DEFAULT 0,
+    public_link TEXT,
+    stars INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- Existing users joined when they linked their oldest identity
+INSERT INTO users_new(id, username, created_at)
+    SELECT id, username, COALESCE(
+        (SELECT MIN(created_at) FROM identities WHERE identities.user_id = users.id),
+        CURRENT_TIMESTAMP
+    )
+    FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+-- The avatar and the profile page of the user on the provider, as given at login
+ALTER TABLE identities ADD COLUMN avatar_url TEXT;
+ALTER TABLE identities ADD COLUMN profile_url TEXT;
@@ -0,0 +1,10 @@
+-- Roles granted to users, giving them privileges such as managing the other users
+CREATE TABLE user_roles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    role VARCHAR(30) NOT NULL,
+    granted_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX user_roles_user_role ON user_roles(user_id, role);
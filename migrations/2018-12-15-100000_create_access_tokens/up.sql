@@ -0,0 +1,15 @@
+-- Personal access tokens, created by the users for their scripts and bots,
+-- and restricted to a few scopes
+CREATE TABLE access_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    token_prefix VARCHAR(8) NOT NULL,
+    token_hash TEXT NOT NULL,
+    scopes TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX access_tokens_token_prefix ON access_tokens(token_prefix);
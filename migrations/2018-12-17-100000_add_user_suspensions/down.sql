@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    display_name VARCHAR(30),
+    anonymous BOOLEAN NOT NULL DEFAULT 0,
+    public_link TEXT,
+    stars INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL
+);
+
+INSERT INTO users_old(id, username, display_name, anonymous, public_link, stars, created_at)
+    SELECT id, username, display_name, anonymous, public_link, stars, created_at FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
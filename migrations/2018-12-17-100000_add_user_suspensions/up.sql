@@ -0,0 +1,5 @@
+-- Suspended users can't log in nor use their tokens, until the end of their suspension if any.
+-- Their data is kept
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN suspension_reason TEXT;
+ALTER TABLE users ADD COLUMN suspended_until TIMESTAMP;
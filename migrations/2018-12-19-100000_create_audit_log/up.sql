@@ -0,0 +1,17 @@
+-- Authentication and administrative events, kept for the admins to look into
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event VARCHAR(30) NOT NULL,
+    user_id INTEGER,
+    actor_id INTEGER,
+    provider TEXT,
+    ip TEXT,
+    user_agent TEXT,
+    details TEXT,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id),
+    FOREIGN KEY(actor_id) REFERENCES users(id)
+);
+
+CREATE INDEX audit_log_user_created_at ON audit_log(user_id, created_at);
+CREATE INDEX audit_log_created_at ON audit_log(created_at);
@@ -0,0 +1,2 @@
+-- Deleted accounts are anonymized rather than removed, so that leaderboards stay consistent
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP;
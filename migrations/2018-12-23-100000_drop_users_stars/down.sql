@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users ADD COLUMN stars INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,22 @@
+-- Stars were never earned anywhere, so the column always read 0
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(30) NOT NULL,
+    display_name VARCHAR(30),
+    anonymous BOOLEAN NOT NULL DEFAULT 0,
+    public_link TEXT,
+    created_at TIMESTAMP NOT NULL,
+    suspended_at TIMESTAMP,
+    suspension_reason TEXT,
+    suspended_until TIMESTAMP,
+    deleted_at TIMESTAMP
+);
+
+INSERT INTO users_new(id, username, display_name, anonymous, public_link, created_at,
+        suspended_at, suspension_reason, suspended_until, deleted_at)
+    SELECT id, username, display_name, anonymous, public_link, created_at,
+        suspended_at, suspension_reason, suspended_until, deleted_at
+    FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
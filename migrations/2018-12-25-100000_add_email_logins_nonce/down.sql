@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE email_logins_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL,
+    token_prefix VARCHAR(8) NOT NULL,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
+
+INSERT INTO email_logins_old(id, email, token_prefix, token_hash, created_at, expires_at)
+    SELECT id, email, token_prefix, token_hash, created_at, expires_at FROM email_logins;
+
+DROP TABLE email_logins;
+ALTER TABLE email_logins_old RENAME TO email_logins;
+
+CREATE UNIQUE INDEX email_logins_token_prefix ON email_logins(token_prefix);
@@ -0,0 +1,3 @@
+-- Login links can only be used from the browser that asked for them, which holds the nonce.
+-- Links sent before have none, and can't be used anymore
+ALTER TABLE email_logins ADD COLUMN nonce_hash TEXT NOT NULL DEFAULT '';
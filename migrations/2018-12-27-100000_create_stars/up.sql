@@ -0,0 +1,11 @@
+-- Stars earned by the users, one per part of a day's puzzle, which make up their totals
+CREATE TABLE stars (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    day INTEGER NOT NULL,
+    part INTEGER NOT NULL,
+    earned_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX stars_user_day_part ON stars(user_id, day, part);
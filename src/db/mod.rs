@@ -1,8 +1,170 @@
-use rocket_contrib::databases::diesel;
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::Error as ConnectionFailure;
+use rocket::config::{Table, Value};
+use rocket_contrib::databases::r2d2::{self, ManageConnection};
+use rocket_contrib::databases::{DatabaseConfig as PoolConfig, Poolable};
+use schema::users;
+use state::database_config::DatabaseConfig;
+use std::ops::Deref;
+
+/// Name of the pool of `DatabaseConn` in the `databases` table of the Rocket configuration
+pub const DATABASE_NAME: &str = "sqlite_db";
 
 #[database("sqlite_db")]
-pub struct DatabaseConn(diesel::SqliteConnection);
+pub struct DatabaseConn(SqliteConn);
 
 #[cfg(test)]
 #[database("test_db")]
-pub struct TestDatabase(diesel::SqliteConnection);
+pub struct TestDatabase(SqliteConn);
+
+/// Connection to the SQLite database, whose pragmas have been set up on opening
+pub struct SqliteConn(SqliteConnection);
+
+impl Deref for SqliteConn {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.0
+    }
+}
+
+/// Pragmas set on every connection, as they don't outlive it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pragmas {
+    /// Milliseconds to wait for a lock held by another connection before failing, if any
+    pub busy_timeout: Option<u32>,
+    /// Whether the database uses a write-ahead log, letting readers go along with a writer
+    pub wal: bool,
+    /// Whether the foreign keys are enforced
+    pub foreign_keys: bool,
+}
+
+impl Pragmas {
+    /// Reads the pragmas from the extra settings of a pool
+    fn from_extras(extras: &Table) -> Self {
+        Pragmas {
+            busy_timeout: extras
+                .get("busy_timeout")
+                .and_then(Value::as_integer)
+                .map(|timeout| timeout as u32),
+            wal: extras.get("wal").and_then(Value::as_bool).unwrap_or(false),
+            foreign_keys: extras
+                .get("foreign_keys")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+
+    /// Gets the statements setting the pragmas
+    fn to_sql(&self) -> String {
+        let mut sql = String::new();
+        if let Some(timeout) = self.busy_timeout {
+            sql.push_str(&format!("PRAGMA busy_timeout = {};", timeout));
+        }
+        if self.wal {
+            sql.push_str("PRAGMA journal_mode = WAL;");
+        }
+        sql.push_str(if self.foreign_keys {
+            "PRAGMA foreign_keys = ON;"
+        } else {
+            "PRAGMA foreign_keys = OFF;"
+        });
+        sql
+    }
+}
+
+/// Opens the connections of a pool, setting their pragmas up
+pub struct SqliteManager {
+    url: String,
+    pragmas: Pragmas,
+}
+
+impl ManageConnection for SqliteManager {
+    type Connection = SqliteConn;
+    type Error = ConnectionFailure;
+
+    fn connect(&self) -> Result<SqliteConn, ConnectionFailure> {
+        let connection =
+            SqliteConnection::establish(&self.url).map_err(ConnectionFailure::ConnectionError)?;
+        connection
+            .batch_execute(&self.pragmas.to_sql())
+            .map_err(ConnectionFailure::QueryError)?;
+        Ok(SqliteConn(connection))
+    }
+
+    fn is_valid(&self, connection: &mut SqliteConn) -> Result<(), ConnectionFailure> {
+        connection
+            .batch_execute("SELECT 1")
+            .map_err(ConnectionFailure::QueryError)
+    }
+
+    fn has_broken(&self, _connection: &mut SqliteConn) -> bool {
+        false
+    }
+}
+
+impl Poolable for SqliteConn {
+    type Manager = SqliteManager;
+    type Error = r2d2::Error;
+
+    fn pool(config: PoolConfig) -> Result<r2d2::Pool<SqliteManager>, r2d2::Error> {
+        let manager = SqliteManager {
+            url: config.url.into(),
+            pragmas: Pragmas::from_extras(&config.extras),
+        };
+        r2d2::Pool::builder()
+            .max_size(config.pool_size)
+            .build(manager)
+    }
+}
+
+/// Opens a connection to the configured database, and makes sure its schema is there, so that
+/// a wrong setup is found out before serving anything
+pub fn check_connection(config: &DatabaseConfig) -> Result<(), String> {
+    let manager = SqliteManager {
+        url: config.get_url().into(),
+        pragmas: config.get_pragmas(),
+    };
+    let connection = manager
+        .connect()
+        .map_err(|e| format!("Failed to open the database {}: {}", config.get_url(), e))?;
+
+    users::table
+        .count()
+        .get_result::<i64>(&*connection)
+        .map(|_| ())
+        .map_err(|e| {
+            format!(
+                "The database {} isn't usable, have its migrations been run? {}",
+                config.get_url(),
+                e
+            )
+        })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Pragmas;
+    use rocket::config::{Table, Value};
+
+    #[test]
+    pub fn read_pragmas() {
+        let mut extras = Table::new();
+        extras.insert("busy_timeout".into(), Value::Integer(2000));
+        extras.insert("wal".into(), Value::Boolean(true));
+        let pragmas = Pragmas::from_extras(&extras);
+        assert_eq!(
+            pragmas,
+            Pragmas {
+                busy_timeout: Some(2000),
+                wal: true,
+                foreign_keys: false,
+            }
+        );
+        assert_eq!(
+            pragmas.to_sql(),
+            "PRAGMA busy_timeout = 2000;PRAGMA journal_mode = WAL;PRAGMA foreign_keys = OFF;"
+        );
+    }
+}
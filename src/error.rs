@@ -0,0 +1,255 @@
+use rocket::http::Status;
+use rocket::request::{self, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::Outcome;
+use rocket_contrib::json::Json;
+use std::fmt;
+
+/// Every way a request to the API, or a login, can fail.
+///
+/// Each error has a stable machine-readable code, and is rendered as
+/// `{ "error": { "code": "...", "message": "..." } }` along with the matching status, so that
+/// the frontend can handle every failure the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// The request doesn't carry any token
+    NoCredentials,
+    /// The token of the request is malformed, unknown or expired
+    InvalidToken,
+    /// The username or the password of a local account is wrong
+    InvalidCredentials,
+    /// The user isn't allowed to do this
+    Forbidden(String),
+    /// The account of the user has been suspended
+    Suspended(String),
+    /// The resource doesn't exist, or doesn't belong to the user
+    NotFound,
+    /// The request conflicts with the current state of the resource
+    Conflict(String),
+    /// The request is well-formed, but its content is invalid
+    Validation(String),
+    /// The request can't be understood
+    BadRequest(String),
+    /// The login attempt can't be trusted, or has expired
+    InvalidLogin(String),
+    /// The same request has been made too often lately
+    TooManyRequests(String),
+    /// An authentication provider failed, or answered something unexpected
+    Provider(String),
+    /// A mail couldn't be sent
+    Mail(String),
+    /// The database failed
+    Database(String),
+    /// Anything else that failed on our side
+    Internal(String),
+}
+
+impl ApiError {
+    /// Gets the stable code identifying the kind of error
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ApiError::NoCredentials => "no_credentials",
+            ApiError::InvalidToken => "invalid_token",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Suspended(_) => "account_suspended",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidLogin(_) => "invalid_login",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Provider(_) => "provider_error",
+            ApiError::Mail(_) => "mail_error",
+            ApiError::Database(_) => "database_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Gets the HTTP status of the error
+    pub fn status(&self) -> Status {
+        match *self {
+            ApiError::NoCredentials | ApiError::InvalidToken | ApiError::InvalidCredentials => {
+                Status::Unauthorized
+            }
+            ApiError::Forbidden(_) | ApiError::Suspended(_) => Status::Forbidden,
+            ApiError::NotFound => Status::NotFound,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::Validation(_) => Status::UnprocessableEntity,
+            ApiError::BadRequest(_) | ApiError::InvalidLogin(_) => Status::BadRequest,
+            ApiError::TooManyRequests(_) => Status::TooManyRequests,
+            ApiError::Provider(_) | ApiError::Mail(_) => Status::BadGateway,
+            ApiError::Database(_) | ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    /// Gets the message shown to the user.
+    /// The details of the failures on our side are only logged
+    pub fn message(&self) -> String {
+        match *self {
+            ApiError::NoCredentials => "No credentials given".into(),
+            ApiError::InvalidToken => "Invalid or expired token".into(),
+            ApiError::InvalidCredentials => "Invalid username or password".into(),
+            ApiError::NotFound => "Not found".into(),
+            ApiError::Mail(_) => "Failed to send the mail".into(),
+            ApiError::Database(_) | ApiError::Internal(_) => "Internal server error".into(),
+            ApiError::Forbidden(ref message)
+            | ApiError::Suspended(ref message)
+            | ApiError::Conflict(ref message)
+            | ApiError::Validation(ref message)
+            | ApiError::BadRequest(ref message)
+            | ApiError::InvalidLogin(ref message)
+            | ApiError::TooManyRequests(ref message)
+            | ApiError::Provider(ref message) => message.clone(),
+        }
+    }
+
+    /// Gets the error matching a status, when nothing more is known about the failure
+    pub fn from_status(status: Status) -> Self {
+        match status.code {
+            400 => ApiError::BadRequest("Malformed request".into()),
+            401 => ApiError::NoCredentials,
+            403 => ApiError::Forbidden("Forbidden".into()),
+            404 => ApiError::NotFound,
+            422 => ApiError::Validation("Invalid request content".into()),
+            _ => ApiError::Internal(format!("Unhandled status {}", status)),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiError::Mail(ref details)
+            | ApiError::Database(ref details)
+            | ApiError::Internal(ref details) => write!(f, "{}: {}", self.code(), details),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => ApiError::NotFound,
+            e => ApiError::Database(format!("{}", e)),
+        }
+    }
+}
+
+/// Body of the response describing an error
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    code: &'static str,
+    message: String,
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        if self.status() == Status::InternalServerError || self.status() == Status::BadGateway {
+            println!("Request to {} failed with {}", request.uri(), self);
+        }
+
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code(),
+                message: self.message(),
+            },
+        };
+        Response::build_from(Json(body).respond_to(request)?)
+            .status(self.status())
+            .ok()
+    }
+}
+
+/// The error a request guard failed with, kept so that the catcher of its status can render it
+struct GuardError(ApiError);
+
+/// Fails a request guard with the given error
+pub fn guard_failure<S>(request: &Request, error: ApiError) -> request::Outcome<S, ApiError> {
+    let status = error.status();
+    request.local_cache(|| GuardError(error.clone()));
+    Outcome::Failure((status, error))
+}
+
+/// Renders the error of a request that failed with the given status, without a handler
+/// describing why. Failures of request guards are found back along the way
+fn caught(request: &Request, status: Status) -> ApiError {
+    let error = &request
+        .local_cache(|| GuardError(ApiError::from_status(status)))
+        .0;
+    if error.status() == status {
+        error.clone()
+    } else {
+        ApiError::from_status(status)
+    }
+}
+
+#[catch(400)]
+pub fn bad_request(request: &Request) -> ApiError {
+    caught(request, Status::BadRequest)
+}
+
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> ApiError {
+    caught(request, Status::Unauthorized)
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request) -> ApiError {
+    caught(request, Status::Forbidden)
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> ApiError {
+    caught(request, Status::NotFound)
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request) -> ApiError {
+    caught(request, Status::UnprocessableEntity)
+}
+
+#[catch(500)]
+pub fn internal_error(request: &Request) -> ApiError {
+    caught(request, Status::InternalServerError)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ApiError;
+    use rocket::http::Status;
+    use rocket::local::Client;
+
+    #[get("/conflict")]
+    fn conflict() -> Result<(), ApiError> {
+        Err(ApiError::Conflict("Already linked".into()))
+    }
+
+    #[test]
+    pub fn render_errors() {
+        let rocket = rocket::ignite()
+            .mount("/", routes![conflict])
+            .register(catchers![super::not_found]);
+        let client = Client::new(rocket).expect("Valid Rocket instance");
+
+        let mut response = client.get("/conflict").dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(
+            response.body_string(),
+            Some(r#"{"error":{"code":"conflict","message":"Already linked"}}"#.into())
+        );
+
+        let mut response = client.get("/missing").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(
+            response.body_string(),
+            Some(r#"{"error":{"code":"not_found","message":"Not found"}}"#.into())
+        );
+    }
+}
@@ -1,13 +1,21 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
+extern crate argon2;
+extern crate base64;
+extern crate chrono;
 #[macro_use]
 extern crate diesel;
+extern crate lettre;
+extern crate lettre_email;
 extern crate rand;
+extern crate reqwest;
+extern crate ring;
 
 #[macro_use]
 extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
+extern crate rocket_cors;
 
 #[macro_use]
 extern crate serde_derive;
@@ -15,6 +23,13 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 
+extern crate toml;
+extern crate untrusted;
+
 pub mod db;
+pub mod error;
+pub mod login;
+pub mod mail;
 pub mod model;
 pub mod schema;
+pub mod state;
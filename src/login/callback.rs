@@ -0,0 +1,157 @@
+use db::DatabaseConn;
+use error::ApiError;
+use login::exchange::redirect_with_code;
+use login::login_state::LoginState;
+use login::provider::OAuthProvider;
+use model::audit_log::{AuditEvent, AuditRecord, ClientIp};
+use model::auth_service::{AuthProvider, AuthService};
+use model::session::UserAgent;
+use model::user::User;
+use reqwest::Client;
+use rocket::http::Cookies;
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use state::global_config::GlobalConfig;
+
+/// Handles callback URL from any configured OAUTH Server
+/// provider: name of the provider (e.g. `github`, `gitlab`)
+/// code: given by the provider, is the API user code that we can transform to an access_token
+/// state: the nonce we generated when starting the login attempt on `/login/<provider>/start`
+/// error: given by the provider instead of the code when the login is refused, e.g. `access_denied`
+#[get("/<provider>?<code>&<state>&<error>")]
+pub fn cb_login(
+    provider: String,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    mut cookies: Cookies,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+) -> Option<Flash<Redirect>> {
+    // Unknown providers are simply not found
+    let provider = config.find_provider(&provider)?;
+
+    // Loads from the config the URL that we're redirecting to
+    let redirect_to: String = provider.redirect().into();
+
+    // We will either fail or succeed to connect, so we Flash the client with a cookie that will
+    // be parsed on the front-end part. On success, the front-end is given a single-use code
+    // to trade for the token on `/login/exchange`, as the token itself mustn't end up in a
+    // cookie readable by any script, nor in the browser's history.
+    let refused = code.is_none();
+    let result_auth = check_state(provider.name(), state, &mut cookies).and_then(|login_state| {
+        let code = code.ok_or_else(|| {
+            ApiError::Provider(format!(
+                "The provider refused the login: {}",
+                error.as_ref().map_or("no code given", String::as_str)
+            ))
+        })?;
+        let user = authenticate(provider, code, &login_state, &config, &db)?;
+        let user_id = user.id.unwrap();
+
+        match login_state.get_link_user() {
+            // Linking an identity doesn't open a new session, the user is already logged in
+            Some(_) => Ok((user_id, None)),
+            // The session is opened once the code is traded
+            None => redirect_with_code(&redirect_to, user_id, &config, &db)
+                .map(|url| (user_id, Some(url))),
+        }
+    });
+
+    // Every attempt is recorded, along with why it failed
+    let record = AuditRecord::new(match result_auth {
+        Ok((_, Some(_))) => AuditEvent::Login,
+        Ok((_, None)) => AuditEvent::IdentityLinked,
+        Err(_) => AuditEvent::LoginFailed,
+    })
+    .with_provider(provider.name())
+    .with_client(&client_ip, &user_agent);
+    match result_auth {
+        Ok((user_id, _)) => record.with_user(user_id),
+        Err(ref e) => record.with_details(e.to_string()),
+    }
+    .record(&db);
+
+    // Following the service's response, we communicate the login code back to the user
+    Some(match result_auth {
+        Ok((_, Some(url))) => Flash::new(Redirect::to(url), "auth_success", provider.name()),
+        Ok((_, None)) => Flash::new(Redirect::to(redirect_to), "link_success", provider.name()),
+        Err(e) => {
+            // The front-end is told why the provider refused the login, as long as it's a
+            // plain OAuth error code
+            let reason = match error {
+                Some(ref error) if refused && is_error_code(error) => error.clone(),
+                _ => e.code().into(),
+            };
+            Flash::new(Redirect::to(redirect_to), "auth_failed", reason)
+        }
+    })
+}
+
+/// Checks that an error given by a provider looks like an OAuth error code, such as
+/// `access_denied`
+fn is_error_code(error: &str) -> bool {
+    !error.is_empty() && error.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+/// Checks that the `state` given to the callback matches the login attempt stored in the cookies,
+/// protecting the callback against login CSRF
+fn check_state(
+    provider: &str,
+    state: Option<String>,
+    cookies: &mut Cookies,
+) -> Result<LoginState, ApiError> {
+    let state = state.ok_or(ApiError::InvalidLogin("No login state given".into()))?;
+    let login_state = LoginState::take(cookies)
+        .ok_or(ApiError::InvalidLogin("No pending login attempt".into()))?;
+    login_state.validate(provider, &state)?;
+    Ok(login_state)
+}
+
+/// Trades the code given by the provider for the identity of the user, and gets the matching
+/// user, or links the identity to the user of the login attempt
+fn authenticate(
+    provider: &dyn OAuthProvider,
+    code: String,
+    login_state: &LoginState,
+    config: &GlobalConfig,
+    db: &diesel::SqliteConnection,
+) -> Result<User, ApiError> {
+    // Trades the code for an access token that will later be used to access the provider's API
+    let client = Client::new();
+    let token = provider.exchange_code(&client, code, login_state)?;
+
+    // Then, we need to know who the user is on the provider
+    let user = provider.identify(&client, &token, login_state)?;
+
+    // Starts the authentication service with our params
+    let cipher = config
+        .borrow_security_config()
+        .get_token_cipher()
+        .map_err(ApiError::Internal)?;
+    let auth_provider_id = AuthProvider::find_or_create(provider.name(), db)?;
+    let mut service = AuthService::new()
+        .with_ext_id(user.ext_id)
+        .with_username(user.username)
+        .with_avatar_url(user.avatar_url)
+        .with_profile_url(user.profile_url)
+        .with_token(cipher.seal(&token.access_token)?)
+        .with_auth_service_id(auth_provider_id)
+        .with_admin_config(config.borrow_admin_config());
+
+    // Some providers, such as Gitlab, give short-lived tokens that have to be refreshed
+    if let Some(ref refresh_token) = token.refresh_token {
+        service = service.with_refresh_token(cipher.seal(refresh_token)?);
+    }
+    if let Some(expires_at) = token.expires_at() {
+        service = service.with_token_expiry(expires_at);
+    }
+
+    if let Some(user_id) = login_state.get_link_user() {
+        service = service.with_linked_user(user_id);
+    }
+
+    service.execute(db)
+}
@@ -0,0 +1,243 @@
+use chrono::Duration;
+use db::DatabaseConn;
+use error::ApiError;
+use login::exchange::redirect_with_code;
+use mail::Mail;
+use model::audit_log::{AuditEvent, AuditRecord, ClientIp};
+use model::auth_service::{AuthProvider, AuthService};
+use model::email_login::EmailLogin;
+use model::session::UserAgent;
+use model::token::random_string;
+use model::user::User;
+use reqwest::Url;
+use rocket::http::{Cookie, Cookies, SameSite};
+use rocket::request::Form;
+use rocket::response::content::Html;
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket_contrib::json::Json;
+use state::email_auth::EmailAuth;
+use state::global_config::GlobalConfig;
+
+/// Maximal length of a username, as stored in the database
+const MAX_USERNAME_LENGTH: usize = 30;
+
+/// Maximal length of an email address (RFC 5321)
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Maximal number of login links of an address that can be used at the same time
+const MAX_PENDING_LINKS: i64 = 3;
+
+/// Name of the private cookie holding the nonce of the browser that asked for a login link
+const NONCE_COOKIE: &str = "email_login";
+
+/// Length of the nonce of the browser that asked for a login link
+const NONCE_LENGTH: usize = 32;
+
+/// Address a login link has to be sent to
+#[derive(Deserialize)]
+pub struct EmailLoginRequest {
+    email: String,
+}
+
+/// Confirmation of a login by the user who followed its link
+#[derive(FromForm)]
+pub struct EmailLoginConfirmation {
+    token: String,
+}
+
+/// Mails a single-use login link to the given address.
+/// Only available when email logins are enabled
+#[post("/email", format = "json", data = "<request>")]
+pub fn start_email_login(
+    request: Json<EmailLoginRequest>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    mut cookies: Cookies,
+) -> Option<Result<(), ApiError>> {
+    let email_config = config.borrow_email_config()?;
+
+    // Addresses are compared regardless of their case
+    let email = request.email.trim().to_lowercase();
+    if !is_valid_email(&email) {
+        return Some(Err(ApiError::Validation("Invalid email address".into())));
+    }
+
+    // Nobody gets flooded with links they didn't ask for
+    match EmailLogin::count_pending(&email, &db) {
+        Ok(count) if count >= MAX_PENDING_LINKS => {
+            return Some(Err(ApiError::TooManyRequests(
+                "Too many login links have been sent to this address lately".into(),
+            )))
+        }
+        Ok(_) => (),
+        Err(e) => return Some(Err(e)),
+    }
+
+    // The link can only be used from this browser, so that it can't be forwarded to someone
+    // else, nor used to log someone into our account
+    let nonce = random_string(NONCE_LENGTH);
+    let result = send_login_link(&email, &nonce, email_config, &config, &db);
+    if result.is_ok() {
+        cookies.add_private(
+            Cookie::build(NONCE_COOKIE, nonce)
+                .path("/login")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(Duration::minutes(email_config.get_link_lifetime()))
+                .finish(),
+        );
+    }
+    Some(result)
+}
+
+/// Handles a login link that has been mailed to a user, by asking them to confirm the login.
+/// Mail scanners following the links they find don't use them up this way
+/// token: the token carried by the link
+#[get("/email/verify?<token>")]
+pub fn verify_email_login(
+    token: String,
+    config: State<GlobalConfig>,
+) -> Option<Result<Html<String>, Flash<Redirect>>> {
+    let email_config = config.borrow_email_config()?;
+
+    // Tokens never need to be escaped, and anything else is rejected
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+        let error = ApiError::InvalidLogin("Invalid or expired login link".into());
+        return Some(Err(Flash::new(
+            Redirect::to(email_config.get_redirect().to_string()),
+            "auth_failed",
+            error.code(),
+        )));
+    }
+
+    Some(Ok(Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Log in</title></head>\n<body>\n\
+         <form method=\"post\" action=\"verify\">\n\
+         <input type=\"hidden\" name=\"token\" value=\"{}\">\n\
+         <button type=\"submit\">Log in</button>\n\
+         </form>\n</body>\n</html>\n",
+        token
+    ))))
+}
+
+/// Uses a login link that has been confirmed by the user, and logs them in
+#[post("/email/verify", data = "<confirmation>")]
+pub fn confirm_email_login(
+    confirmation: Form<EmailLoginConfirmation>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+    mut cookies: Cookies,
+) -> Option<Flash<Redirect>> {
+    let email_config = config.borrow_email_config()?;
+    let redirect_to: String = email_config.get_redirect().into();
+    let nonce = cookies
+        .get_private(NONCE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .unwrap_or_default();
+
+    // The front-end trades the login code for the token on `/login/exchange`
+    let result_auth = authenticate(&confirmation.token, &nonce, email_config, &config, &db)
+        .and_then(|user| {
+            let user_id = user.id.unwrap();
+            redirect_with_code(&redirect_to, user_id, &config, &db).map(|url| (user_id, url))
+        });
+
+    let record = AuditRecord::new(match result_auth {
+        Ok(_) => AuditEvent::Login,
+        Err(_) => AuditEvent::LoginFailed,
+    })
+    .with_provider(email_config.get_name())
+    .with_client(&client_ip, &user_agent);
+    match result_auth {
+        Ok((user_id, _)) => record.with_user(user_id),
+        Err(ref e) => record.with_details(e.to_string()),
+    }
+    .record(&db);
+
+    if result_auth.is_ok() {
+        cookies.remove_private(Cookie::build(NONCE_COOKIE, "").path("/login").finish());
+    }
+    Some(match result_auth {
+        Ok((_, url)) => Flash::new(Redirect::to(url), "auth_success", email_config.get_name()),
+        Err(e) => Flash::new(Redirect::to(redirect_to), "auth_failed", e.code()),
+    })
+}
+
+/// Creates a login for the given address, from the browser holding the given nonce, and mails
+/// its link
+fn send_login_link(
+    email: &str,
+    nonce: &str,
+    email_config: &EmailAuth,
+    config: &GlobalConfig,
+    db: &diesel::SqliteConnection,
+) -> Result<(), ApiError> {
+    let hasher = config.borrow_security_config().get_token_hasher();
+    let token = EmailLogin::create(email, email_config.get_link_lifetime(), nonce, &hasher, db)?;
+    let link = Url::parse_with_params(email_config.get_link_url(), &[("token", token)])
+        .map_err(|e| ApiError::Internal(format!("Invalid login link address: {}", e)))?;
+
+    let mail = Mail {
+        to: email.into(),
+        subject: "Your login link".into(),
+        body: format!(
+            "Follow this link to log in:\n{}\n\nIt can only be used once, within {} minutes, \
+             from the browser you asked it from.\n\
+             If you didn't try to log in, you can safely ignore this mail.",
+            link,
+            email_config.get_link_lifetime()
+        ),
+    };
+    email_config.get_sender().send(&mail)
+}
+
+/// Uses the login link carrying the given token, from the browser holding the given nonce, and
+/// gets the matching user
+fn authenticate(
+    token: &str,
+    nonce: &str,
+    email_config: &EmailAuth,
+    config: &GlobalConfig,
+    db: &diesel::SqliteConnection,
+) -> Result<User, ApiError> {
+    let hasher = config.borrow_security_config().get_token_hasher();
+    let email = EmailLogin::consume(token, nonce, &hasher, db)
+        .map_err(|_| ApiError::InvalidLogin("Invalid or expired login link".into()))?;
+
+    // The address is the identity of the user on this provider
+    let auth_provider_id = AuthProvider::find_or_create(email_config.get_name(), db)?;
+    AuthService::new()
+        .with_ext_id(email.clone())
+        .with_username(username_from_email(&email))
+        .with_auth_service_id(auth_provider_id)
+        .with_admin_config(config.borrow_admin_config())
+        .execute(db)
+}
+
+/// Checks that an address looks like a valid one. The mail itself is the real check
+fn is_valid_email(email: &str) -> bool {
+    let mut parts = email.splitn(2, '@');
+    match (parts.next(), parts.next()) {
+        (Some(local), Some(domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && email.len() <= MAX_EMAIL_LENGTH
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        _ => false,
+    }
+}
+
+/// Derives a username from an address, as the part before the `@`
+fn username_from_email(email: &str) -> String {
+    email
+        .split('@')
+        .next()
+        .unwrap_or(email)
+        .chars()
+        .take(MAX_USERNAME_LENGTH)
+        .collect()
+}
@@ -0,0 +1,72 @@
+use db::DatabaseConn;
+use error::ApiError;
+use model::login_code::LoginCode;
+use model::session::{Session, UserAgent};
+use reqwest::Url;
+use rocket::http::{Cookie, Cookies, SameSite};
+use rocket::State;
+use rocket_contrib::json::Json;
+use state::global_config::GlobalConfig;
+
+/// A login code to trade for the token of a new session
+#[derive(Deserialize)]
+pub struct LoginExchange {
+    code: String,
+    /// Whether the token has to be set as an HttpOnly cookie instead of being sent back
+    #[serde(default)]
+    set_cookie: bool,
+}
+
+/// Token of the session opened by trading a login code.
+/// It is left out when it has been set as a cookie
+#[derive(Serialize)]
+pub struct ExchangedToken {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// Trades a login code, given to the frontend at the end of a login, for the token of a new session
+#[post("/exchange", format = "json", data = "<exchange>")]
+pub fn exchange_code(
+    exchange: Json<LoginExchange>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    mut cookies: Cookies,
+    user_agent: UserAgent,
+) -> Result<Json<ExchangedToken>, ApiError> {
+    let hasher = config.borrow_security_config().get_token_hasher();
+    let user_id = LoginCode::consume(&exchange.code, &hasher, &db)
+        .map_err(|_| ApiError::InvalidLogin("Invalid or expired login code".into()))?;
+    let (_, token) = Session::create(user_id, user_agent.0, &hasher, &db)?;
+
+    if !exchange.set_cookie {
+        return Ok(Json(ExchangedToken { token: Some(token) }));
+    }
+
+    // The cookie can't be read by any script, and is only sent along with requests from our site
+    cookies.add(
+        Cookie::build("api_token", token)
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .finish(),
+    );
+    Ok(Json(ExchangedToken { token: None }))
+}
+
+/// Issues a login code for the given user, and builds the address of the frontend to redirect to,
+/// carrying the code as the `login_code` parameter
+pub fn redirect_with_code(
+    redirect: &str,
+    user_id: i32,
+    config: &GlobalConfig,
+    db: &diesel::SqliteConnection,
+) -> Result<String, ApiError> {
+    let hasher = config.borrow_security_config().get_token_hasher();
+    let code = LoginCode::create(user_id, &hasher, db)?;
+
+    Url::parse_with_params(redirect, &[("login_code", code)])
+        .map(|url| url.into_string())
+        .map_err(|e| ApiError::Internal(format!("Invalid redirect address {}: {}", redirect, e)))
+}
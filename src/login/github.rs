@@ -1,142 +1,78 @@
-use db::DatabaseConn;
-use model::auth_service::AuthService;
+use error::ApiError;
+use login::provider::{OAuthProvider, ProviderUser};
 use reqwest::Client;
-use rocket::response::{Flash, Redirect};
-use rocket::State;
 use serde_json::Value;
-use state::global_config::GlobalConfig;
+use state::github::GithubAuth;
+use std::collections::HashMap;
 
-/// Handles callback URL from Github's OAUTH Server
-/// code: given by github, is the API user code that we can transform to an access_token
-#[get("/github?<code>")]
-pub fn cb_login_github(
-    code: String,
-    config: State<GlobalConfig>,
-    db: DatabaseConn,
-) -> Flash<Redirect> {
-    // Gets the Github configuration
-    let github_config = config.borrow_github_config();
-
-    // Gets the code given by Github and creates a POST request to Github's OAUTH server
-    // Hopefully gets a result containing an acces_token that will later be used to access
-    // the Github API.
-    let client = Client::new();
-    let result_acces_token = client
-        .post("https://github.com/login/oauth/access_token")
-        .header("Accept", "application/json")
-        .json(&AccessTokenRequestBody::new(
-            github_config.get_client_id().into(),
-            github_config.get_secret().into(),
-            code,
-        ))
-        .send();
-
-    // Create a reply from the given Github access token
-    let reply: GithubAuthReply = match result_acces_token {
-        Ok(mut res) => {
-            let access_token_response: Result<AccessTokenResponse, _> = res.json();
-
-            match access_token_response {
-                Ok(response) => GithubAuthReply {
-                    success: true,
-                    message: response.access_token,
-                },
-                _ => GithubAuthReply {
-                    success: false,
-                    message: "Error contacting Github".into(),
-                },
-            }
-        }
-        _ => GithubAuthReply {
-            success: false,
-            message: "Github's server did not respond".into(),
-        },
-    };
-
-    // Loads from the config the URL that we're redirecting to
-    let redirect_to: String = format!("{}", github_config.get_redirect());
+impl OAuthProvider for GithubAuth {
+    fn name(&self) -> &str {
+        self.get_name()
+    }
 
-    // We will either failed or success to disconnect, so we Flash the client with a cookie that will
-    // be parsed on the front-end part.
+    fn kind(&self) -> &'static str {
+        "github"
+    }
 
-    // If we didn't succeed to get a correct Github response, we flash the client with an error
-    if !reply.success {
-        return Flash::new(Redirect::to(redirect_to), "auth_failed", reply.message);
+    fn client_id(&self) -> &str {
+        self.get_client_id()
     }
 
-    // Otherwise, lets try to connect ourselves :)
-    // First, we need to get the user's Github name
-    let username_query = client
-        .get("https://api.github.com/user")
-        .header("Authorization", format!("token {}", reply.message.clone()))
-        .header("Accept", "application/json")
-        .send();
+    fn secret(&self) -> &str {
+        self.get_secret()
+    }
 
-    // If we got a correct response from Github, we can get the username
-    match username_query {
-        Ok(mut res) => {
-            // Parses the JSON from Github
-            let value: Value = res.json().expect("Failed to read JSON");
+    fn redirect(&self) -> &str {
+        self.get_redirect()
+    }
 
-            // Gets the username; Trims it and removes quotes because Github's username format is weird
-            let username: String = format!("{}", value["login"])
-                .trim()
-                .chars()
-                .filter(|&c| c != '\"')
-                .collect();
+    fn authorize_endpoint(&self) -> Result<String, ApiError> {
+        Ok(format!("{}/login/oauth/authorize", self.get_base_url()))
+    }
 
-            // Starts the authentication service with our params
-            let result_auth = AuthService::new()
-                .with_username(username)
-                .with_token(reply.message)
-                .with_auth_service_id(1)
-                .execute(&db);
+    fn token_endpoint(&self) -> Result<String, ApiError> {
+        Ok(format!("{}/login/oauth/access_token", self.get_base_url()))
+    }
 
-            // Following the service's response, we communicate the custom token back to the user, using a Flash
-            match result_auth {
-                Ok(user) => Flash::new(Redirect::to(redirect_to), "auth_success", user.token),
-                Err(e) => Flash::new(Redirect::to(redirect_to), "auth_failed", e),
-            }
-        }
-        _ => Flash::new(
-            Redirect::to(redirect_to),
-            "auth_failed",
-            format!("Failed to get the username from Github"),
-        ),
+    fn user_endpoint(&self) -> Result<String, ApiError> {
+        Ok(format!("{}/user", self.get_api_url()))
     }
-}
 
-/// Represents a response sent to the client, either indicating that we've successfully connected
-/// or not
-struct GithubAuthReply {
-    success: bool,
-    message: String,
-}
+    fn scopes(&self) -> Vec<&str> {
+        vec!["read:user"]
+    }
 
-/// Struct that is serialized and sent to Github in order to OAUTH an user
-#[derive(Serialize, Deserialize)]
-struct AccessTokenRequestBody {
-    client_id: String,
-    client_secret: String,
-    code: String,
-}
+    fn extract_user(&self, user: &Value) -> Option<ProviderUser> {
+        Some(ProviderUser {
+            ext_id: user["id"].as_i64()?.to_string(),
+            username: user["login"].as_str()?.into(),
+            avatar_url: user["avatar_url"].as_str().map(String::from),
+            profile_url: user["html_url"].as_str().map(String::from),
+        })
+    }
 
-/// Parses the Access token response from Github
-/// Probably overkill. TODO (refactor): remove
-#[derive(Serialize, Deserialize, Debug)]
-struct AccessTokenResponse {
-    pub access_token: String,
-    token_type: String,
-    scope: String,
-}
+    /// Github doesn't follow RFC 7009, and revokes every token of the app at once
+    /// by deleting the grant of the user
+    fn revoke_token(&self, client: &Client, token: &str) -> Result<bool, ApiError> {
+        let mut body = HashMap::new();
+        body.insert("access_token", token);
+
+        client
+            .delete(&format!(
+                "{}/applications/{}/grant",
+                self.get_api_url(),
+                self.get_client_id()
+            ))
+            .basic_auth(self.get_client_id(), Some(self.get_secret()))
+            .json(&body)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map(|_| true)
+            .map_err(|_| ApiError::Provider(format!("Failed to revoke a token of {}", self.name())))
+    }
 
-impl AccessTokenRequestBody {
-    /// Creates a new auth request body
-    pub fn new(id: String, secret: String, code: String) -> Self {
-        AccessTokenRequestBody {
-            client_id: id,
-            client_secret: secret,
-            code,
-        }
+    /// Github expects the `token` scheme rather than `Bearer`
+    fn authorization_header(&self, access_token: &str) -> String {
+        format!("token {}", access_token)
     }
 }
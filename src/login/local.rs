@@ -0,0 +1,264 @@
+use db::DatabaseConn;
+use diesel::Connection;
+use error::ApiError;
+use model::audit_log::{AuditEvent, AuditRecord, ClientIp};
+use model::auth_service::{AuthProvider, AuthService};
+use model::identity::Identity;
+use model::local_credential::LocalCredential;
+use model::role::UserRole;
+use model::session::{Session, UserAgent};
+use model::user::{APIUser, User};
+use rocket::State;
+use rocket_contrib::json::Json;
+use state::admin_config::AdminConfig;
+use state::global_config::GlobalConfig;
+use state::local_auth::LocalAuth;
+
+/// Maximal length of a username, as stored in the database
+const MAX_USERNAME_LENGTH: usize = 30;
+
+/// Username and password of a local account
+#[derive(Deserialize)]
+pub struct LocalCredentials {
+    username: String,
+    password: String,
+}
+
+/// Request to change the password of a local account
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+/// Internal token of the session opened by a local login
+#[derive(Serialize)]
+pub struct LocalLogin {
+    token: String,
+}
+
+/// Creates a local account and logs in with it.
+/// Only available when local accounts are enabled
+#[post("/local/register", format = "json", data = "<credentials>")]
+pub fn register(
+    credentials: Json<LocalCredentials>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+) -> Option<Result<Json<LocalLogin>, ApiError>> {
+    let local = config.borrow_local_config()?;
+
+    // The login is only recorded once its session is open
+    let result = create_account(local, config.borrow_admin_config(), &credentials, &db)
+        .and_then(|user_id| open_session(user_id, &user_agent, &config, &db));
+    record_login(local, &credentials, &result, &client_ip, &user_agent, &db);
+    Some(result.map(|(_, login)| login))
+}
+
+/// Logs in with a local account.
+/// Only available when local accounts are enabled
+#[post("/local", format = "json", data = "<credentials>")]
+pub fn login(
+    credentials: Json<LocalCredentials>,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    client_ip: ClientIp,
+    user_agent: UserAgent,
+) -> Option<Result<Json<LocalLogin>, ApiError>> {
+    let local = config.borrow_local_config()?;
+
+    let result = check_credentials(local, config.borrow_admin_config(), &credentials, &db)
+        .and_then(|user_id| open_session(user_id, &user_agent, &config, &db));
+    record_login(local, &credentials, &result, &client_ip, &user_agent, &db);
+    Some(result.map(|(_, login)| login))
+}
+
+/// Changes the password of the local account of the user.
+/// Every other session of the user is closed, as they may have been opened with the old password
+#[put("/me/password", format = "json", data = "<change>")]
+pub fn change_password(
+    change: Json<PasswordChange>,
+    api_user: APIUser,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+) -> Option<Result<(), ApiError>> {
+    let local = config.borrow_local_config()?;
+
+    Some(update_password(local, &change, &api_user, &db))
+}
+
+/// Creates the user, its local identity and its password. Returns the ID of the user
+fn create_account(
+    local: &LocalAuth,
+    admin: Option<&AdminConfig>,
+    credentials: &LocalCredentials,
+    db: &diesel::SqliteConnection,
+) -> Result<i32, ApiError> {
+    if !local.is_registration_open() {
+        return Err(ApiError::Forbidden("Registration is closed".into()));
+    }
+    if !is_valid_username(&credentials.username) {
+        return Err(ApiError::Validation(format!(
+            "A username has at most {} letters, digits, '-', '_' or '.'",
+            MAX_USERNAME_LENGTH
+        )));
+    }
+    check_password(local, &credentials.password)?;
+
+    // Usernames are unique regardless of their case
+    let auth_provider = local_provider_id(local, db)?;
+    let ext_id = credentials.username.to_lowercase();
+
+    // An account without a password would keep its username taken for good
+    db.transaction(|| {
+        if Identity::find_by_ext_id(&ext_id, auth_provider, db).is_some() {
+            return Err(ApiError::Conflict("This username is already taken".into()));
+        }
+
+        let user = AuthService::new()
+            .with_ext_id(ext_id.clone())
+            .with_username(credentials.username.clone())
+            .with_auth_service_id(auth_provider)
+            .with_admin_config(admin)
+            .execute(db)?;
+        let identity = Identity::find_by_ext_id(&ext_id, auth_provider, db).ok_or(
+            ApiError::Internal(format!("The identity of {} is missing", ext_id)),
+        )?;
+        LocalCredential::create(identity.id.unwrap(), &credentials.password, db)?;
+
+        Ok(user.id.unwrap())
+    })
+}
+
+/// Checks the username and the password of a local account. Returns the ID of its user
+fn check_credentials(
+    local: &LocalAuth,
+    admin: Option<&AdminConfig>,
+    credentials: &LocalCredentials,
+    db: &diesel::SqliteConnection,
+) -> Result<i32, ApiError> {
+    let auth_provider = local_provider_id(local, db)?;
+    let ext_id = credentials.username.to_lowercase();
+    let identity = Identity::find_by_ext_id(&ext_id, auth_provider, db);
+    let credential = identity
+        .as_ref()
+        .and_then(|identity| LocalCredential::find_for_identity(identity.id.unwrap(), db));
+
+    // A password is hashed either way, so that the response time doesn't tell which usernames
+    // exist
+    let verified = match credential {
+        Some(ref credential) => credential.verify(&credentials.password),
+        None => LocalCredential::verify_missing(&credentials.password),
+    };
+    let identity = match identity {
+        Some(identity) if verified => identity,
+        _ => return Err(ApiError::InvalidCredentials),
+    };
+
+    // Suspended users are told so once they proved who they are
+    User::find_by_id(identity.user_id, db)
+        .map_err(|_| ApiError::Internal(format!("No user found for this account")))?
+        .check_not_suspended()?;
+    if let Some(admin) = admin {
+        UserRole::bootstrap_admin(admin, auth_provider, &ext_id, identity.user_id, db)?;
+    }
+    Ok(identity.user_id)
+}
+
+/// Replaces the password of the local account of the user, once the current one is checked
+fn update_password(
+    local: &LocalAuth,
+    change: &PasswordChange,
+    api_user: &APIUser,
+    db: &diesel::SqliteConnection,
+) -> Result<(), ApiError> {
+    let session_id = api_user.require_session()?;
+    check_password(local, &change.new_password)?;
+
+    let auth_provider = local_provider_id(local, db)?;
+    let identity =
+        Identity::find_for_user(api_user.id, auth_provider, db).ok_or(ApiError::NotFound)?;
+    let mut credential =
+        LocalCredential::find_for_identity(identity.id.unwrap(), db).ok_or(ApiError::NotFound)?;
+    if !credential.verify(&change.current_password) {
+        return Err(ApiError::Forbidden("The current password is wrong".into()));
+    }
+
+    credential.set_password(&change.new_password, db)?;
+    Session::revoke_others(api_user.id, session_id, db).map(|_| ())
+}
+
+/// Opens a new session for the user, and gives back the ID of the user along with its token
+fn open_session(
+    user_id: i32,
+    user_agent: &UserAgent,
+    config: &GlobalConfig,
+    db: &diesel::SqliteConnection,
+) -> Result<(i32, Json<LocalLogin>), ApiError> {
+    let hasher = config.borrow_security_config().get_token_hasher();
+    Session::create(user_id, user_agent.0.clone(), &hasher, db)
+        .map(|(_, token)| (user_id, Json(LocalLogin { token })))
+}
+
+/// Records a login attempt, or a registration, with a local account
+fn record_login(
+    local: &LocalAuth,
+    credentials: &LocalCredentials,
+    result: &Result<(i32, Json<LocalLogin>), ApiError>,
+    client_ip: &ClientIp,
+    user_agent: &UserAgent,
+    db: &diesel::SqliteConnection,
+) {
+    let record = AuditRecord::new(match *result {
+        Ok(_) => AuditEvent::Login,
+        Err(_) => AuditEvent::LoginFailed,
+    })
+    .with_provider(local.get_name())
+    .with_client(client_ip, user_agent);
+    match *result {
+        Ok((user_id, _)) => record.with_user(user_id),
+        // Failures on an existing account are tied to its user, so that they go along with it
+        Err(ref e) => match existing_user_id(local, &credentials.username, db) {
+            Some(user_id) => record.with_user(user_id).with_details(e.to_string()),
+            None => record.with_details(format!("{}: {}", credentials.username, e)),
+        },
+    }
+    .record(db);
+}
+
+/// Gets the ID of the user owning the local account with the given username, if it exists
+fn existing_user_id(
+    local: &LocalAuth,
+    username: &str,
+    db: &diesel::SqliteConnection,
+) -> Option<i32> {
+    let auth_provider = local_provider_id(local, db).ok()?;
+    Identity::find_by_ext_id(&username.to_lowercase(), auth_provider, db)
+        .map(|identity| identity.user_id)
+}
+
+/// Gets the ID of the `AuthProvider` of the local accounts
+fn local_provider_id(local: &LocalAuth, db: &diesel::SqliteConnection) -> Result<i32, ApiError> {
+    AuthProvider::find_or_create(local.get_name(), db)
+}
+
+/// Checks that a username can be used for a local account
+fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LENGTH
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Checks that a password is long enough
+fn check_password(local: &LocalAuth, password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < local.get_min_password_length() {
+        return Err(ApiError::Validation(format!(
+            "A password has at least {} characters",
+            local.get_min_password_length()
+        )));
+    }
+    Ok(())
+}
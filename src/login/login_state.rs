@@ -0,0 +1,144 @@
+use error::ApiError;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use ring::digest;
+use rocket::http::{Cookie, Cookies, SameSite};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the private cookie holding the pending login attempt
+const STATE_COOKIE: &str = "oauth_state";
+
+/// Number of seconds a login attempt stays valid
+const STATE_MAX_AGE: u64 = 10 * 60;
+
+/// Describes a login attempt that has been started on `/login/<provider>/start`
+/// and that is waiting for the provider to call us back.
+///
+/// It is stored in an encrypted cookie, so that the callback can check that the `state`
+/// it was given has really been emitted by us, for this very browser.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginState {
+    /// Name of the provider the user is logging in with
+    provider: String,
+    /// Random value sent to the provider as the `state` parameter
+    nonce: String,
+    /// UNIX timestamp of the start of the login attempt
+    issued_at: u64,
+    /// ID of the logged in user linking a new identity, if this is not a regular login
+    link_user: Option<i32>,
+    /// Secret proving that the code given to the callback has been requested by us (PKCE)
+    code_verifier: String,
+}
+
+impl LoginState {
+    /// Starts a new login attempt for the given provider
+    pub fn new(provider: &str) -> Self {
+        LoginState {
+            provider: provider.into(),
+            nonce: random_string(32),
+            issued_at: now(),
+            link_user: None,
+            code_verifier: random_string(64),
+        }
+    }
+
+    /// Starts a new attempt to link an identity of the given provider to a logged in user
+    pub fn for_linking(provider: &str, user_id: i32) -> Self {
+        LoginState {
+            link_user: Some(user_id),
+            ..LoginState::new(provider)
+        }
+    }
+
+    /// Gets the ID of the user linking a new identity, if any
+    pub fn get_link_user(&self) -> Option<i32> {
+        self.link_user
+    }
+
+    /// Gets the value to send to the provider as the `state` parameter
+    pub fn get_nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    /// Gets the PKCE secret to send along with the code when trading it for a token
+    pub fn get_code_verifier(&self) -> &str {
+        &self.code_verifier
+    }
+
+    /// Gets the PKCE challenge to send to the provider when starting the login attempt,
+    /// which is the base64url-encoded SHA-256 of the verifier
+    pub fn get_code_challenge(&self) -> String {
+        let hash = digest::digest(&digest::SHA256, self.code_verifier.as_bytes());
+        base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Saves the login attempt in the client's cookies
+    pub fn store(&self, cookies: &mut Cookies) {
+        let value = serde_json::to_string(self).expect("Failed to serialize the login state");
+        cookies.add_private(
+            Cookie::build(STATE_COOKIE, value)
+                .path("/login")
+                .http_only(true)
+                // The provider redirects the user to us, so the cookie must survive
+                // a cross-site top-level navigation
+                .same_site(SameSite::Lax)
+                .finish(),
+        );
+    }
+
+    /// Takes the pending login attempt out of the client's cookies.
+    /// A login attempt can only be used once.
+    pub fn take(cookies: &mut Cookies) -> Option<Self> {
+        let cookie = cookies.get_private(STATE_COOKIE)?;
+        cookies.remove_private(Cookie::build(STATE_COOKIE, "").path("/login").finish());
+        serde_json::from_str(cookie.value()).ok()
+    }
+
+    /// Checks that the callback of the given provider, called with the given state,
+    /// matches this login attempt
+    pub fn validate(&self, provider: &str, state: &str) -> Result<(), ApiError> {
+        if self.provider != provider || self.nonce != state {
+            return Err(ApiError::InvalidLogin("Invalid login state".into()));
+        }
+
+        if now().saturating_sub(self.issued_at) > STATE_MAX_AGE {
+            return Err(ApiError::InvalidLogin("Login attempt expired".into()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates a random alphanumeric string of the given length
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .collect()
+}
+
+/// Gets the current UNIX timestamp
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::LoginState;
+
+    #[test]
+    pub fn code_challenge() {
+        // Example given in the appendix B of RFC 7636
+        let login_state = LoginState {
+            code_verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".into(),
+            ..LoginState::new("test_provider")
+        };
+        assert_eq!(
+            login_state.get_code_challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
@@ -1,2 +1,12 @@
+pub mod callback;
+pub mod email;
+pub mod exchange;
 pub mod github;
 pub mod gitlab;
+pub mod local;
+pub mod login_state;
+pub mod oidc;
+pub mod provider;
+pub mod provider_client;
+pub mod providers;
+pub mod start;
@@ -0,0 +1,467 @@
+use chrono::Utc;
+use error::ApiError;
+use login::login_state::LoginState;
+use login::provider::{OAuthProvider, ProviderToken, ProviderUser};
+use login::provider_client::ProviderClient;
+use reqwest::Client;
+use ring::signature;
+use serde_json::Value;
+use state::oidc::OidcAuth;
+use std::time::{Duration, Instant};
+use untrusted::Input;
+
+/// Number of seconds an ID token is still accepted after its expiry, to account for clock skew
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// Minimal number of seconds between two fetches of the signing keys of a provider
+const KEYS_REFETCH_SECONDS: u64 = 60;
+
+/// Metadata published by an OpenID Connect provider at `.well-known/openid-configuration`
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+    pub revocation_endpoint: Option<String>,
+}
+
+/// A public key of an OpenID Connect provider, as published in its JSON Web Key Set
+#[derive(Deserialize, Clone, Debug)]
+pub struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    /// Modulus of a RSA key, base64url-encoded
+    n: Option<String>,
+    /// Exponent of a RSA key, base64url-encoded
+    e: Option<String>,
+}
+
+/// Signing keys of a provider, along with when they have last been fetched
+#[derive(Default, Debug)]
+pub struct KeyCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+impl KeyCache {
+    /// Checks whether the keys can be fetched again
+    fn can_refetch(&self) -> bool {
+        self.fetched_at.map_or(true, |fetched_at| {
+            fetched_at.elapsed() >= Duration::from_secs(KEYS_REFETCH_SECONDS)
+        })
+    }
+}
+
+/// Document listing the public keys of a provider
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+impl OAuthProvider for OidcAuth {
+    fn name(&self) -> &str {
+        self.get_name()
+    }
+
+    fn kind(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn client_id(&self) -> &str {
+        self.get_client_id()
+    }
+
+    fn secret(&self) -> &str {
+        self.get_secret()
+    }
+
+    fn redirect(&self) -> &str {
+        self.get_redirect()
+    }
+
+    fn authorize_endpoint(&self) -> Result<String, ApiError> {
+        self.metadata()
+            .map(|metadata| metadata.authorization_endpoint)
+    }
+
+    fn token_endpoint(&self) -> Result<String, ApiError> {
+        self.metadata().map(|metadata| metadata.token_endpoint)
+    }
+
+    fn user_endpoint(&self) -> Result<String, ApiError> {
+        self.metadata()?
+            .userinfo_endpoint
+            .ok_or(ApiError::Provider(format!(
+                "{} has no userinfo endpoint",
+                self.name()
+            )))
+    }
+
+    fn scopes(&self) -> Vec<&str> {
+        self.get_scopes()
+    }
+
+    fn revocation_endpoint(&self) -> Result<Option<String>, ApiError> {
+        self.metadata().map(|metadata| metadata.revocation_endpoint)
+    }
+
+    fn extract_user(&self, user: &Value) -> Option<ProviderUser> {
+        Some(ProviderUser {
+            ext_id: user["sub"].as_str()?.into(),
+            username: user["preferred_username"].as_str()?.into(),
+            avatar_url: user["picture"].as_str().map(String::from),
+            profile_url: user["profile"].as_str().map(String::from),
+        })
+    }
+
+    /// The redirect URI is mandatory for OpenID Connect
+    fn callback_uri(&self) -> Option<&str> {
+        Some(self.get_redirect_api())
+    }
+
+    fn uses_pkce(&self) -> bool {
+        true
+    }
+
+    /// The user is identified by the signed ID token. The userinfo endpoint is only queried
+    /// when the ID token doesn't carry the username
+    fn identify(
+        &self,
+        client: &Client,
+        token: &ProviderToken,
+        login_state: &LoginState,
+    ) -> Result<ProviderUser, ApiError> {
+        let id_token = token.id_token.as_ref().ok_or(ApiError::Provider(format!(
+            "{} did not give an ID token",
+            self.name()
+        )))?;
+        let claims = self.verify_id_token(id_token, login_state.get_nonce())?;
+        if let Some(user) = self.extract_user(&claims) {
+            return Ok(user);
+        }
+
+        let user = self.fetch_user(&ProviderClient::new(client, self, &token.access_token))?;
+        if claims["sub"].as_str() != Some(user.ext_id.as_str()) {
+            return Err(ApiError::Provider(format!(
+                "{} described another user",
+                self.name()
+            )));
+        }
+        Ok(user)
+    }
+}
+
+impl OidcAuth {
+    /// Gets the metadata of the provider, discovering it on first use
+    fn metadata(&self) -> Result<ProviderMetadata, ApiError> {
+        let mut cache = self.get_metadata_cache().lock().map_err(|_| {
+            ApiError::Internal(format!("Failed to read the metadata of {}", self.name()))
+        })?;
+        if let Some(ref metadata) = *cache {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.get_issuer());
+        let metadata: ProviderMetadata = Client::new()
+            .get(&url)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json())
+            .map_err(|_| {
+                ApiError::Provider(format!("Failed to discover {} at {}", self.name(), url))
+            })?;
+
+        // The provider must be the issuer we have been configured with
+        if metadata.issuer.trim_end_matches('/') != self.get_issuer() {
+            return Err(ApiError::Provider(format!(
+                "{} claims to be {} instead of {}",
+                self.name(),
+                metadata.issuer,
+                self.get_issuer()
+            )));
+        }
+
+        *cache = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Finds the signing key with the given ID. The keys are fetched again when the key is
+    /// unknown, as providers rotate them, but at most once a minute: anyone can send an ID token
+    /// signed with an unknown key, which mustn't make us hammer the provider
+    fn find_key(&self, kid: Option<&str>) -> Result<Jwk, ApiError> {
+        let poisoned = || ApiError::Internal(format!("Failed to read the keys of {}", self.name()));
+        let unknown = || ApiError::Provider(format!("Unknown signing key for {}", self.name()));
+
+        {
+            let mut cache = self.get_keys_cache().lock().map_err(|_| poisoned())?;
+            if let Some(key) = find_jwk(&cache.keys, kid) {
+                return Ok(key.clone());
+            }
+            if !cache.can_refetch() {
+                return Err(unknown());
+            }
+            cache.fetched_at = Some(Instant::now());
+        }
+
+        // The lock isn't held while fetching, so that other logins aren't blocked meanwhile
+        let jwks_uri = self.metadata()?.jwks_uri;
+        let key_set: JwkSet = Client::new()
+            .get(&jwks_uri)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json())
+            .map_err(|_| {
+                ApiError::Provider(format!("Failed to get the keys of {}", self.name()))
+            })?;
+
+        let mut cache = self.get_keys_cache().lock().map_err(|_| poisoned())?;
+        cache.keys = key_set.keys;
+        find_jwk(&cache.keys, kid).cloned().ok_or_else(unknown)
+    }
+
+    /// Checks the signature and the claims of an ID token, and returns its claims
+    fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<Value, ApiError> {
+        let error = || ApiError::Provider(format!("Invalid ID token given by {}", self.name()));
+
+        let parts: Vec<&str> = id_token.split('.').collect();
+        if parts.len() != 3 {
+            return Err(error());
+        }
+
+        // Every provider supports RS256, which is the only algorithm we accept
+        let header = decode_json(parts[0]).ok_or_else(error)?;
+        if header["alg"].as_str() != Some("RS256") {
+            return Err(ApiError::Provider(format!(
+                "{} signed its ID token with an unsupported algorithm",
+                self.name()
+            )));
+        }
+
+        let key = self.find_key(header["kid"].as_str())?;
+        let n = key.n.as_ref().and_then(|n| decode(n)).ok_or_else(error)?;
+        let e = key.e.as_ref().and_then(|e| decode(e)).ok_or_else(error)?;
+        let signature = decode(parts[2]).ok_or_else(error)?;
+        let message = &id_token[..parts[0].len() + 1 + parts[1].len()];
+        signature::primitive::verify_rsa(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            (Input::from(&n), Input::from(&e)),
+            Input::from(message.as_bytes()),
+            Input::from(&signature),
+        )
+        .map_err(|_| error())?;
+
+        let claims = decode_json(parts[1]).ok_or_else(error)?;
+        let issuer = self.metadata()?.issuer;
+        validate_claims(
+            &claims,
+            &issuer,
+            self.client_id(),
+            nonce,
+            Utc::now().timestamp(),
+        )?;
+        Ok(claims)
+    }
+}
+
+/// Finds the RSA signing key with the given ID, or the first one if the ID token doesn't tell
+fn find_jwk<'a>(keys: &'a [Jwk], kid: Option<&str>) -> Option<&'a Jwk> {
+    keys.iter()
+        .filter(|key| key.kty == "RSA" && key.key_use.as_ref().map_or(true, |u| u == "sig"))
+        .find(|key| kid.is_none() || key.kid.as_ref().map(String::as_str) == kid)
+}
+
+/// Checks that the claims of an ID token have been issued by the given issuer, for us, for the
+/// given login attempt, and that they have not expired
+fn validate_claims(
+    claims: &Value,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<(), ApiError> {
+    if claims["iss"].as_str() != Some(issuer) {
+        return Err(ApiError::Provider(format!(
+            "The ID token has been issued by someone else"
+        )));
+    }
+
+    let audience_ok = match claims["aud"] {
+        Value::String(ref aud) => aud == client_id,
+        Value::Array(ref aud) => aud.iter().any(|aud| aud.as_str() == Some(client_id)),
+        _ => false,
+    };
+    let party_ok = claims["azp"].as_str().map_or(true, |azp| azp == client_id);
+    if !audience_ok || !party_ok {
+        return Err(ApiError::Provider(format!(
+            "The ID token has been issued for someone else"
+        )));
+    }
+
+    match claims["exp"].as_i64() {
+        Some(exp) if exp + CLOCK_SKEW_SECONDS > now => (),
+        _ => return Err(ApiError::Provider(format!("The ID token has expired"))),
+    }
+
+    if claims["nonce"].as_str() != Some(nonce) {
+        return Err(ApiError::InvalidLogin(format!(
+            "The ID token belongs to another login attempt"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Decodes a base64url-encoded part of a JSON Web Token
+fn decode(value: &str) -> Option<Vec<u8>> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// Decodes a base64url-encoded JSON part of a JSON Web Token
+fn decode_json(value: &str) -> Option<Value> {
+    serde_json::from_slice(&decode(value)?).ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{validate_claims, ProviderMetadata};
+    use state::oidc::OidcAuth;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Modulus of the RSA key signing the test ID tokens, generated for these tests only
+    const TEST_KEY_N: &str = "oDKT6FqP_uN8BA0XLRnaT-Z-2uftZt4IXAGx91g1yPmcK81uoLILYpYW7qhH4cOyiTvPM\
+        fX-WZKbnijAg-lkEJhRXzuwiW8_msMFnKwEkGtI_5XXmBakFYzNTxFSd3-4Gim5E4JBLiKFe3-saDl29FGcRmOqKCy9\
+        TWWvHCSgHcN6b5kt-0aVNwU3WmZr4l6Gh8hk3pIA1dGiGADF1292Z83oaa7TYSZaOGJCwXUh6uF7TgdL9J-XzlWRD2W\
+        ldLH8QzhL5JTuUGDic-yxJOv4tPpe73MkVY8S7dkEqs3B6mHaHYbWPfdcX1T2w24K8Yo3gUjBm__N8W25TZ0YaIFdAw";
+
+    /// ID token signed with the test key, announced as `test-key`
+    const ID_TOKEN: &str = "eyJhbGciOiJSUzI1NiIsImtpZCI6InRlc3Qta2V5In0.eyJpc3MiOiJodHRwczovL2lkc\
+        C5leGFtcGxlLmNvbSIsImF1ZCI6ImFvYzE4IiwiZXhwIjo0MTAyNDQ0ODAwLCJub25jZSI6ImxvZ2luX25vbmNlIiw\
+        ic3ViIjoiZjNhMSIsInByZWZlcnJlZF91c2VybmFtZSI6ImFsaWNlIn0.D5evyUTWZKKAhOZrEIiaLsn4pTkwBCSIVgE\
+        EvzRmAhjnEoKPBPVOCPoXAxhb63x1-1QnbU9c7OPG2UR1sghPwyBGd3io6XVBM6KPGf3UotqNXbbcMimciAm-l43ripG\
+        VMKjX0TiQ8OEEj99tAmiNeVVb0jY0Uc1rMAIo51doe6TSt9lCMhr_3yQgUqNIHUsIpSyI5RZp6BLTBdEA6OLUj4cDOMi\
+        ECSU02C-eJ1WqDZC_xx_UGX8Ri1QD66fwP-3Ty9qg8_Er6Xr_FK_y9e7LCtQFvM7qh19JpStlJ_ZGVWyrutdfUsJT6u4\
+        ToYcPw8cJqGeHwfSbz6YXA618rffRAA";
+
+    /// The same claims signed with the same key, but announced as an unknown `rotated-key`
+    const ROTATED_ID_TOKEN: &str = "eyJhbGciOiJSUzI1NiIsImtpZCI6InJvdGF0ZWQta2V5In0.eyJpc3MiOiJod\
+        HRwczovL2lkcC5leGFtcGxlLmNvbSIsImF1ZCI6ImFvYzE4IiwiZXhwIjo0MTAyNDQ0ODAwLCJub25jZSI6ImxvZ2lu\
+        X25vbmNlIiwic3ViIjoiZjNhMSIsInByZWZlcnJlZF91c2VybmFtZSI6ImFsaWNlIn0.YqDoF1NPbIRKg2ccCLj2RlZF\
+        mWkCqpXSle6m_C4w-DTQQtDTR8zhmfsxvGZZYwHhTqe9GukiKDuFyDuXRslepbZwWepgfDlULaQSRHTY9-c4Jevxnc8\
+        K-VJDhVUJJ_aZNLD-dj-UPWlAiIDkscrTBcTuwmUEzI1HNSRfs3XQMdPjfKvqBmXF1WiZRdXHWSXM5iidYsS_QzcNmaf\
+        NmU_ZLDFWI7CsgXnKJm1ACah-DDEPQY_v4l3q8cfhBjV8xAHIv5fG7XSkn1W0cORiTbXScN3bl1066QOwjq8ftB7RZrP\
+        _ll-T1UfS5jIealJBEePEg4HYbLKiFE2EplvVi84HIg";
+
+    /// Serves the key set of a fake provider, and counts how many times it has been fetched
+    fn serve_key_set() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Bound fake provider");
+        let address = listener.local_addr().expect("Fake provider address");
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let counter = fetches.clone();
+        thread::spawn(move || {
+            let body = format!(
+                r#"{{"keys":[{{"kid":"test-key","kty":"RSA","use":"sig","alg":"RS256","n":"{}","e":"AQAB"}}]}}"#,
+                TEST_KEY_N
+            );
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        (format!("http://{}/jwks", address), fetches)
+    }
+
+    #[test]
+    pub fn verify_id_token_signature() {
+        let (jwks_uri, fetches) = serve_key_set();
+        let provider: OidcAuth = toml::from_str(
+            r#"
+            issuer = "https://idp.example.com"
+            client_id = "aoc18"
+            secret = "secret"
+            redirect = "http://localhost/"
+            redirect_api = "http://localhost/login/oidc"
+            "#,
+        )
+        .expect("Valid OpenID Connect config");
+        *provider.get_metadata_cache().lock().unwrap() = Some(ProviderMetadata {
+            issuer: "https://idp.example.com".into(),
+            authorization_endpoint: "https://idp.example.com/authorize".into(),
+            token_endpoint: "https://idp.example.com/token".into(),
+            userinfo_endpoint: None,
+            jwks_uri,
+            revocation_endpoint: None,
+        });
+
+        // The keys are fetched on first use, then kept
+        let claims = provider
+            .verify_id_token(ID_TOKEN, "login_nonce")
+            .expect("Valid ID token");
+        assert_eq!(claims["sub"].as_str(), Some("f3a1"));
+        assert!(provider.verify_id_token(ID_TOKEN, "login_nonce").is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Tampered tokens and tokens of other login attempts are refused
+        let tampered = ID_TOKEN.replace(".eyJpc3Mi", ".eyJpc3mi");
+        assert!(provider.verify_id_token(&tampered, "login_nonce").is_err());
+        assert!(provider.verify_id_token(ID_TOKEN, "other_nonce").is_err());
+
+        // Unknown keys don't make us fetch the keys again right away
+        assert!(provider
+            .verify_id_token(ROTATED_ID_TOKEN, "login_nonce")
+            .is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    pub fn validate_id_token_claims() {
+        let claims = serde_json::from_str(
+            r#"{
+                "iss": "https://idp.example.com/realms/aoc",
+                "aud": ["aoc18", "account"],
+                "azp": "aoc18",
+                "exp": 1000,
+                "nonce": "login_nonce",
+                "sub": "f3a1"
+            }"#,
+        )
+        .expect("Valid claims");
+        let issuer = "https://idp.example.com/realms/aoc";
+
+        assert!(validate_claims(&claims, issuer, "aoc18", "login_nonce", 900).is_ok());
+        // Tokens of other issuers, clients or login attempts are refused
+        assert!(validate_claims(
+            &claims,
+            "https://evil.example.com",
+            "aoc18",
+            "login_nonce",
+            900
+        )
+        .is_err());
+        assert!(validate_claims(&claims, issuer, "other_client", "login_nonce", 900).is_err());
+        assert!(validate_claims(&claims, issuer, "aoc18", "other_nonce", 900).is_err());
+        // Expired tokens are refused
+        assert!(validate_claims(&claims, issuer, "aoc18", "login_nonce", 2000).is_err());
+    }
+}
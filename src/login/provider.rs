@@ -0,0 +1,252 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use error::ApiError;
+use login::login_state::LoginState;
+use login::provider_client::ProviderClient;
+use reqwest::{Client, Url};
+use serde_json::Value;
+
+/// Describes an external OAUTH server that users can authenticate against.
+///
+/// Every provider follows the same flow : the code given to the callback is exchanged for an
+/// access token, which is then used to query the provider's API for the user's information.
+/// Implementors only have to describe what differs between providers.
+///
+/// Endpoints may have to be discovered from the provider itself, which can fail.
+pub trait OAuthProvider {
+    /// Name of the provider, as used in the login routes (e.g. `/login/github`)
+    /// and as the name of the matching row in the `authprovider` table
+    fn name(&self) -> &str;
+
+    /// Kind of the provider (e.g. `github`), telling the frontend how to present it
+    fn kind(&self) -> &'static str;
+
+    /// Gets the client ID of the OAUTH app
+    fn client_id(&self) -> &str;
+
+    /// Gets the secret identifier of the OAUTH app
+    fn secret(&self) -> &str;
+
+    /// Gets the address to redirect to after a login attempt
+    fn redirect(&self) -> &str;
+
+    /// URL of the page where the user authorizes our app
+    fn authorize_endpoint(&self) -> Result<String, ApiError>;
+
+    /// URL of the endpoint that trades a code for an access token
+    fn token_endpoint(&self) -> Result<String, ApiError>;
+
+    /// URL of the API endpoint describing the authenticated user
+    fn user_endpoint(&self) -> Result<String, ApiError>;
+
+    /// Scopes requested from the provider
+    fn scopes(&self) -> Vec<&str>;
+
+    /// Extracts the user's information from the JSON returned by the user endpoint
+    fn extract_user(&self, user: &Value) -> Option<ProviderUser>;
+
+    /// The redirect URI registered on the provider, if it has to be sent back
+    /// when exchanging the code
+    fn callback_uri(&self) -> Option<&str> {
+        None
+    }
+
+    /// URL of the endpoint revoking tokens (RFC 7009), if the provider has one
+    fn revocation_endpoint(&self) -> Result<Option<String>, ApiError> {
+        Ok(None)
+    }
+
+    /// Whether the code exchange is protected with PKCE (RFC 7636)
+    fn uses_pkce(&self) -> bool {
+        false
+    }
+
+    /// Value of the `Authorization` header used to query the provider's API
+    fn authorization_header(&self, access_token: &str) -> String {
+        format!("Bearer {}", access_token)
+    }
+
+    /// Builds the URL the user is sent to in order to start the given login attempt
+    fn authorize_url(&self, login_state: &LoginState) -> Result<String, ApiError> {
+        let scopes = self.scopes().join(" ");
+        let challenge = login_state.get_code_challenge();
+        let mut params = vec![
+            ("client_id", self.client_id()),
+            ("response_type", "code"),
+            ("scope", scopes.as_str()),
+            ("state", login_state.get_nonce()),
+        ];
+        if let Some(uri) = self.callback_uri() {
+            params.push(("redirect_uri", uri));
+        }
+        if self.uses_pkce() {
+            params.push(("code_challenge", challenge.as_str()));
+            params.push(("code_challenge_method", "S256"));
+        }
+        // OpenID Connect providers copy the nonce in the ID token, binding it to this attempt
+        if self.scopes().contains(&"openid") {
+            params.push(("nonce", login_state.get_nonce()));
+        }
+
+        Url::parse_with_params(&self.authorize_endpoint()?, &params)
+            .map(|url| url.into_string())
+            .map_err(|e| {
+                ApiError::Internal(format!("Invalid authorize URL for {}: {}", self.name(), e))
+            })
+    }
+
+    /// Trades the code given to the callback of the given login attempt for an access token
+    fn exchange_code(
+        &self,
+        client: &Client,
+        code: String,
+        login_state: &LoginState,
+    ) -> Result<ProviderToken, ApiError> {
+        let code_verifier = if self.uses_pkce() {
+            Some(login_state.get_code_verifier().into())
+        } else {
+            None
+        };
+        let body = AccessTokenRequestBody {
+            client_id: self.client_id().into(),
+            client_secret: self.secret().into(),
+            grant_type: "authorization_code".into(),
+            code: Some(code),
+            refresh_token: None,
+            redirect_uri: self.callback_uri().map(Into::into),
+            code_verifier,
+        };
+
+        request_token(self, client, &body)
+    }
+
+    /// Trades a refresh token for a new access token, once the previous one has expired
+    fn refresh_token(
+        &self,
+        client: &Client,
+        refresh_token: String,
+    ) -> Result<ProviderToken, ApiError> {
+        let body = AccessTokenRequestBody {
+            client_id: self.client_id().into(),
+            client_secret: self.secret().into(),
+            grant_type: "refresh_token".into(),
+            code: None,
+            refresh_token: Some(refresh_token),
+            redirect_uri: self.callback_uri().map(Into::into),
+            code_verifier: None,
+        };
+
+        request_token(self, client, &body)
+    }
+
+    /// Revokes a token given by the provider, once the user deleted its account.
+    /// Returns whether the provider supports it
+    fn revoke_token(&self, client: &Client, token: &str) -> Result<bool, ApiError> {
+        let endpoint = match self.revocation_endpoint()? {
+            Some(endpoint) => endpoint,
+            None => return Ok(false),
+        };
+
+        client
+            .post(&endpoint)
+            .form(&[
+                ("token", token),
+                ("client_id", self.client_id()),
+                ("client_secret", self.secret()),
+            ])
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map(|_| true)
+            .map_err(|_| ApiError::Provider(format!("Failed to revoke a token of {}", self.name())))
+    }
+
+    /// Queries the provider's API to get the information of the user the client acts for
+    fn fetch_user(&self, client: &ProviderClient) -> Result<ProviderUser, ApiError> {
+        let error = || ApiError::Provider(format!("Failed to get the user from {}", self.name()));
+
+        let mut res = client
+            .get(&self.user_endpoint()?)
+            .send()
+            .map_err(|_| error())?;
+
+        let value: Value = res.json().map_err(|_| error())?;
+        self.extract_user(&value).ok_or_else(error)
+    }
+
+    /// Gets the user who logged in with the given login attempt, given the tokens it ended with
+    fn identify(
+        &self,
+        client: &Client,
+        token: &ProviderToken,
+        _login_state: &LoginState,
+    ) -> Result<ProviderUser, ApiError> {
+        self.fetch_user(&ProviderClient::new(client, self, &token.access_token))
+    }
+}
+
+/// Describes a user, as known by a provider
+#[derive(Debug)]
+pub struct ProviderUser {
+    /// Immutable identifier of the user on the provider, which survives renames
+    pub ext_id: String,
+    /// Current username of the user on the provider
+    pub username: String,
+    /// Address of the avatar of the user, if it has one
+    pub avatar_url: Option<String>,
+    /// Address of the profile page of the user on the provider, if any
+    pub profile_url: Option<String>,
+}
+
+/// Tokens given by a provider in exchange of a code or of a refresh token
+#[derive(Deserialize, Debug)]
+pub struct ProviderToken {
+    /// The token used to access the provider's API on behalf of the user
+    pub access_token: String,
+    /// The token used to get a new access token once this one expires, if the provider uses them
+    pub refresh_token: Option<String>,
+    /// Number of seconds the access token stays valid, if it expires at all
+    pub expires_in: Option<i64>,
+    /// The signed identity of the user, given by OpenID Connect providers
+    pub id_token: Option<String>,
+}
+
+impl ProviderToken {
+    /// Gets the date after which the access token can't be used anymore
+    pub fn expires_at(&self) -> Option<NaiveDateTime> {
+        self.expires_in
+            .map(|seconds| Utc::now().naive_utc() + Duration::seconds(seconds))
+    }
+}
+
+/// Struct that is serialized and sent to the provider in order to OAUTH an user
+#[derive(Serialize, Deserialize)]
+struct AccessTokenRequestBody {
+    client_id: String,
+    client_secret: String,
+    grant_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_verifier: Option<String>,
+}
+
+/// Sends a request to the token endpoint of the provider, and parses the given tokens
+fn request_token<P: OAuthProvider + ?Sized>(
+    provider: &P,
+    client: &Client,
+    body: &AccessTokenRequestBody,
+) -> Result<ProviderToken, ApiError> {
+    // The token endpoint expects a form, as described by the OAUTH specification (RFC 6749)
+    let mut res = client
+        .post(&provider.token_endpoint()?)
+        .header("Accept", "application/json")
+        .form(body)
+        .send()
+        .map_err(|_| ApiError::Provider(format!("{}'s server did not respond", provider.name())))?;
+
+    res.json()
+        .map_err(|_| ApiError::Provider(format!("Error contacting {}", provider.name())))
+}
@@ -0,0 +1,109 @@
+use chrono::{Duration, Utc};
+use error::ApiError;
+use login::provider::OAuthProvider;
+use model::auth_service::AuthProvider;
+use model::encrypted_token::TokenCipher;
+use model::identity::Identity;
+use reqwest::{Client, RequestBuilder};
+
+/// Number of seconds before the expiry of an access token from which it gets refreshed
+const REFRESH_MARGIN_SECONDS: i64 = 60;
+
+/// HTTP client querying the API of a provider on behalf of a user.
+///
+/// Every call to the API of a provider goes through it : the access token of the user
+/// is transparently refreshed beforehand if it has expired, and is sent with every request.
+pub struct ProviderClient {
+    client: Client,
+    /// Value of the `Authorization` header carrying the access token of the user
+    authorization: String,
+}
+
+impl ProviderClient {
+    /// Creates a client using an access token that has just been given by the provider
+    pub fn new<P: OAuthProvider + ?Sized>(
+        client: &Client,
+        provider: &P,
+        access_token: &str,
+    ) -> Self {
+        ProviderClient {
+            client: client.clone(),
+            authorization: provider.authorization_header(access_token),
+        }
+    }
+
+    /// Creates a client acting on behalf of a user, through the stored tokens of its identity
+    /// on the given provider
+    pub fn for_identity(
+        identity: &mut Identity,
+        provider: &dyn OAuthProvider,
+        cipher: &TokenCipher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Self, ApiError> {
+        if identity.auth_provider != AuthProvider::find_or_create(provider.name(), db)? {
+            return Err(ApiError::Internal(format!(
+                "The identity doesn't belong to {}",
+                provider.name()
+            )));
+        }
+
+        let client = Client::new();
+        if needs_refresh(identity) {
+            let refresh_token = match identity.ext_refresh_token {
+                Some(ref refresh_token) => cipher.open(refresh_token)?,
+                None => {
+                    return Err(ApiError::Provider(format!(
+                        "The access token to {} has expired",
+                        provider.name()
+                    )))
+                }
+            };
+
+            // Providers may or may not give a new refresh token along with the access token
+            let token = provider.refresh_token(&client, refresh_token)?;
+            let ext_refresh_token = match token.refresh_token {
+                Some(ref refresh_token) => Some(cipher.seal(refresh_token)?),
+                None => identity.ext_refresh_token.clone(),
+            };
+            let ext_token = cipher.seal(&token.access_token)?;
+            identity.set_ext_tokens(ext_token, ext_refresh_token, token.expires_at(), db)?;
+        }
+
+        let access_token = match identity.ext_token {
+            Some(ref ext_token) => cipher.open(ext_token)?,
+            None => {
+                return Err(ApiError::Provider(format!(
+                    "No access token to {}",
+                    provider.name()
+                )))
+            }
+        };
+        Ok(ProviderClient::new(&client, provider, &access_token))
+    }
+
+    /// Starts a GET request to the provider's API
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.authorize(self.client.get(url))
+    }
+
+    /// Starts a POST request to the provider's API
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.authorize(self.client.post(url))
+    }
+
+    /// Adds the access token of the user to a request
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header("Authorization", self.authorization.as_str())
+            .header("Accept", "application/json")
+    }
+}
+
+/// Checks whether the access token of the identity has expired, or is about to
+fn needs_refresh(identity: &Identity) -> bool {
+    let limit = Utc::now().naive_utc() + Duration::seconds(REFRESH_MARGIN_SECONDS);
+    identity
+        .ext_token_expires_at
+        .map(|expires_at| expires_at <= limit)
+        .unwrap_or(false)
+}
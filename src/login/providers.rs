@@ -0,0 +1,34 @@
+use rocket::State;
+use rocket_contrib::json::Json;
+use state::global_config::GlobalConfig;
+
+/// A way to log in that is enabled, so that the frontend can show the matching button
+#[derive(Serialize)]
+pub struct EnabledProvider {
+    /// Name of the provider, as used in the login routes
+    name: String,
+    /// Kind of the provider: `github`, `gitlab`, `oidc`, `local` or `email`
+    kind: &'static str,
+}
+
+/// Lists every enabled way to log in
+#[get("/providers")]
+pub fn list_providers(config: State<GlobalConfig>) -> Json<Vec<EnabledProvider>> {
+    let mut providers: Vec<EnabledProvider> = config
+        .providers()
+        .into_iter()
+        .map(|provider| EnabledProvider {
+            name: provider.name().into(),
+            kind: provider.kind(),
+        })
+        .collect();
+    providers.extend(config.borrow_local_config().map(|local| EnabledProvider {
+        name: local.get_name().into(),
+        kind: "local",
+    }));
+    providers.extend(config.borrow_email_config().map(|email| EnabledProvider {
+        name: email.get_name().into(),
+        kind: "email",
+    }));
+    Json(providers)
+}
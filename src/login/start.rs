@@ -0,0 +1,66 @@
+use login::login_state::LoginState;
+use login::provider::OAuthProvider;
+use model::user::APIUser;
+use rocket::http::Cookies;
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use state::global_config::GlobalConfig;
+
+/// Starts a login attempt on the given provider
+/// Generates a `state` nonce, remembers it in an encrypted cookie, and redirects the user
+/// to the provider's authorization page
+#[get("/<provider>/start")]
+pub fn start_login(
+    provider: String,
+    config: State<GlobalConfig>,
+    mut cookies: Cookies,
+) -> Option<Result<Redirect, Flash<Redirect>>> {
+    // Unknown providers are simply not found
+    let provider = config.find_provider(&provider)?;
+
+    let login_state = LoginState::new(provider.name());
+    Some(redirect_to_provider(provider, login_state, &mut cookies))
+}
+
+/// Starts linking an identity of the given provider to the logged in user
+/// Works as a login attempt, except that the callback will link the identity instead of
+/// logging in
+#[get("/me/identities/<provider>/link")]
+pub fn link_identity(
+    provider: String,
+    api_user: APIUser,
+    config: State<GlobalConfig>,
+    mut cookies: Cookies,
+) -> Option<Result<Redirect, Flash<Redirect>>> {
+    // Unknown providers are simply not found
+    let provider = config.find_provider(&provider)?;
+    if let Err(e) = api_user.require_session() {
+        return Some(Err(Flash::new(
+            Redirect::to(provider.redirect().to_string()),
+            "auth_failed",
+            e.code(),
+        )));
+    }
+
+    let login_state = LoginState::for_linking(provider.name(), api_user.id);
+    Some(redirect_to_provider(provider, login_state, &mut cookies))
+}
+
+/// Remembers the login attempt and redirects the user to the provider's authorization page
+fn redirect_to_provider(
+    provider: &dyn OAuthProvider,
+    login_state: LoginState,
+    cookies: &mut Cookies,
+) -> Result<Redirect, Flash<Redirect>> {
+    match provider.authorize_url(&login_state) {
+        Ok(url) => {
+            login_state.store(cookies);
+            Ok(Redirect::to(url))
+        }
+        Err(e) => Err(Flash::new(
+            Redirect::to(provider.redirect().to_string()),
+            "auth_failed",
+            e.code(),
+        )),
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::Utc;
+use error::ApiError;
+use mail::{Mail, MailSender};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Writes each mail in its own file of a directory instead of sending it, for development
+pub struct FileSender {
+    /// Directory the mails are written to
+    directory: PathBuf,
+    /// Address the mails are sent from
+    from: String,
+}
+
+impl FileSender {
+    /// Creates a sender writing to the given directory, which has to exist
+    pub fn new(directory: &str, from: &str) -> Self {
+        FileSender {
+            directory: directory.into(),
+            from: from.into(),
+        }
+    }
+}
+
+impl MailSender for FileSender {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        let now = Utc::now();
+        let path = self.directory.join(format!(
+            "{}_{}.eml",
+            now.timestamp_nanos(),
+            file_name_part(&mail.to)
+        ));
+
+        let mut file = File::create(&path)
+            .map_err(|e| ApiError::Mail(format!("Failed to create {:?}: {}", path, e)))?;
+        write!(
+            file,
+            "Date: {}\r\nFrom: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            now.to_rfc2822(),
+            self.from,
+            mail.to,
+            mail.subject,
+            mail.body
+        )
+        .map_err(|e| ApiError::Mail(format!("Failed to write {:?}: {}", path, e)))
+    }
+}
+
+/// Keeps only the characters of an address that are safe in a file name, so that it can't
+/// point outside of the directory
+fn file_name_part(address: &str) -> String {
+    address
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '@' | '-' | '+' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::file_name_part;
+
+    #[test]
+    pub fn keep_addresses_in_directory() {
+        assert_eq!(file_name_part("a.b+c@example.com"), "a_b+c@example_com");
+        assert_eq!(file_name_part("../../etc/x@y"), "______etc_x@y");
+    }
+}
@@ -0,0 +1,12 @@
+use error::ApiError;
+use mail::{Mail, MailSender};
+
+/// Prints the mails on the standard output instead of sending them, for development
+pub struct LogSender;
+
+impl MailSender for LogSender {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        println!("Mail to {} : {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use error::ApiError;
+
+pub mod file;
+pub mod log;
+pub mod smtp;
+
+/// A plain text mail to send to a user
+#[derive(Debug)]
+pub struct Mail {
+    /// Address of the recipient
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Describes a way of delivering mails to the users
+pub trait MailSender {
+    /// Sends a mail, from the address of the platform
+    fn send(&self, mail: &Mail) -> Result<(), ApiError>;
+}
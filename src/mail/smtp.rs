@@ -0,0 +1,72 @@
+use error::ApiError;
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::{ClientSecurity, SmtpTransport, SmtpTransportBuilder};
+use lettre::EmailTransport;
+use lettre_email::EmailBuilder;
+use mail::{Mail, MailSender};
+
+/// Sends the mails through a SMTP server
+pub struct SmtpSender {
+    /// Address of the SMTP server
+    host: String,
+    /// Port of the SMTP server, when TLS isn't used
+    port: u16,
+    /// Username and password to authenticate against the server, if it requires it
+    credentials: Option<(String, String)>,
+    /// Whether the connection is encrypted with STARTTLS on the submission port
+    tls: bool,
+    /// Address the mails are sent from
+    from: String,
+}
+
+impl SmtpSender {
+    /// Creates a sender going through the given server
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        tls: bool,
+        from: &str,
+    ) -> Self {
+        SmtpSender {
+            host: host.into(),
+            port,
+            credentials,
+            tls,
+            from: from.into(),
+        }
+    }
+
+    /// Opens a connection to the server
+    fn transport(&self) -> Result<SmtpTransport, ApiError> {
+        let error = |e| ApiError::Mail(format!("Failed to connect to {}: {}", self.host, e));
+        let mut builder = if self.tls {
+            SmtpTransport::simple_builder(&self.host).map_err(error)?
+        } else {
+            SmtpTransportBuilder::new((self.host.as_str(), self.port), ClientSecurity::None)
+                .map_err(error)?
+        };
+
+        if let Some((ref username, ref password)) = self.credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.build())
+    }
+}
+
+impl MailSender for SmtpSender {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        let email = EmailBuilder::new()
+            .to(mail.to.as_str())
+            .from(self.from.as_str())
+            .subject(mail.subject.as_str())
+            .text(mail.body.as_str())
+            .build()
+            .map_err(|e| ApiError::Mail(format!("Invalid mail: {}", e)))?;
+
+        self.transport()?
+            .send(&email)
+            .map(|_| ())
+            .map_err(|e| ApiError::Mail(format!("Failed to send the mail to {}: {}", mail.to, e)))
+    }
+}
@@ -1,7 +1,12 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
+extern crate argon2;
+extern crate base64;
+extern crate chrono;
 #[macro_use]
 extern crate diesel;
+extern crate lettre;
+extern crate lettre_email;
 
 extern crate rand;
 
@@ -12,6 +17,7 @@ extern crate rocket_contrib;
 extern crate rocket_cors;
 
 extern crate reqwest;
+extern crate ring;
 
 #[macro_use]
 extern crate serde_derive;
@@ -20,17 +26,24 @@ extern crate serde;
 extern crate serde_json;
 
 extern crate toml;
+extern crate untrusted;
 
 pub mod db;
+pub mod error;
 pub mod login;
+pub mod mail;
 pub mod model;
 pub mod schema;
 pub mod state;
 
 use db::DatabaseConn;
-use rocket::http::Method;
-use rocket_cors::{AllowedHeaders, AllowedOrigins};
+use model::auth_service::AuthProvider;
+use model::identity::Identity;
+use model::session::Session;
+use rocket::config::{Config, ConfigError, RocketConfig, Value};
 use state::global_config::GlobalConfig;
+use std::collections::HashMap;
+use std::process;
 
 #[get("/")]
 fn index() -> &'static str {
@@ -40,36 +53,167 @@ fn index() -> &'static str {
 fn main() {
     // Load config
     println!("Loading config ...");
-    let config: GlobalConfig = GlobalConfig::load();
+    let config: GlobalConfig = match GlobalConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load the config: {}", e);
+            process::exit(1);
+        }
+    };
+    // The keys have already been checked along with the config
+    let cipher = match config.borrow_security_config().get_token_cipher() {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            eprintln!("Invalid keys for the external tokens: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // The CORS policy has already been checked along with the config as well
+    let cors = match config.borrow_cors_config().get_cors() {
+        Ok(cors) => cors,
+        Err(e) => {
+            eprintln!("Invalid CORS policy: {}", e);
+            process::exit(1);
+        }
+    };
     println!("Config loaded successfully !");
 
-    // Setup CORS Options
-    let (allowed_origins, failed_origins) = AllowedOrigins::some(&[
-        "http://192.168.1.1:8080",
-        "http://192.168.1.1:8000",
-        "http://localhost",
-    ]);
-    let cors_options = rocket_cors::Cors {
-        allowed_origins: allowed_origins,
-        allowed_methods: vec![Method::Get].into_iter().map(From::from).collect(),
-        allowed_headers: AllowedHeaders::some(&["Authorization", "Accept"]),
-        allow_credentials: true,
-        ..Default::default()
+    // Fails right away, rather than on the first request, if the database can't be used
+    if let Err(e) = db::check_connection(config.borrow_database_config()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    // Only the routes of the enabled ways to log in are mounted
+    let mut login_routes = routes![
+        login::providers::list_providers,
+        login::exchange::exchange_code
+    ];
+    let mut api_routes = routes![
+        model::user::get_username,
+        model::user::get_me,
+        model::user::update_me,
+        model::account::export_account,
+        model::account::delete_account,
+        model::role::list_users,
+        model::role::grant_role,
+        model::role::revoke_role,
+        model::role::suspend_user,
+        model::role::unsuspend_user,
+        model::audit_log::get_audit_log,
+        model::session::logout,
+        model::session::get_sessions,
+        model::session::delete_session,
+        model::identity::get_identities,
+        model::identity::delete_identity,
+        model::access_token::get_tokens,
+        model::access_token::create_token,
+        model::access_token::delete_token,
+        model::star::get_leaderboard
+    ];
+    if !config.providers().is_empty() {
+        login_routes.extend(routes![
+            login::start::start_login,
+            login::callback::cb_login
+        ]);
+        api_routes.extend(routes![
+            login::start::link_identity,
+            model::identity::refresh_identity
+        ]);
+    }
+    if config.borrow_local_config().is_some() {
+        login_routes.extend(routes![login::local::register, login::local::login]);
+        api_routes.extend(routes![login::local::change_password]);
+    }
+    if config.borrow_email_config().is_some() {
+        login_routes.extend(routes![
+            login::email::start_email_login,
+            login::email::verify_email_login,
+            login::email::confirm_email_login
+        ]);
+    }
+
+    // The server itself is set up by Rocket.toml, but the database comes from our config.
+    // Without Rocket.toml, the defaults of the active environment are used, as Rocket does
+    let rocket_config = match RocketConfig::read() {
+        Ok(rocket_config) => Ok(rocket_config.active().clone()),
+        Err(ConfigError::NotFound) => Config::active(),
+        Err(e) => Err(e),
     };
+    let mut rocket_config = match rocket_config {
+        Ok(rocket_config) => rocket_config,
+        Err(e) => {
+            eprintln!("Failed to load Rocket.toml: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut extras: HashMap<String, Value> = rocket_config
+        .extras()
+        .map(|(name, value)| (name.into(), value.clone()))
+        .collect();
+    extras.insert(
+        "databases".into(),
+        config.borrow_database_config().get_rocket_databases(),
+    );
+    rocket_config.set_extras(extras);
 
-    println!("Launching the server ...");
-    rocket::ignite()
+    let rocket = rocket::custom(rocket_config)
         .manage(config)
-        .attach(DatabaseConn::fairing())
-        .attach(cors_options)
+        .attach(DatabaseConn::fairing());
+
+    // Registers every configured provider, and makes sure every external token is encrypted
+    // with the current key
+    let db = DatabaseConn::get_one(&rocket).expect("Failed to connect to the database");
+    for provider in rocket.state::<GlobalConfig>().unwrap().providers() {
+        AuthProvider::find_or_create(provider.name(), &db).expect("Failed to register provider");
+    }
+    if let Some(local) = rocket
+        .state::<GlobalConfig>()
+        .unwrap()
+        .borrow_local_config()
+    {
+        AuthProvider::find_or_create(local.get_name(), &db).expect("Failed to register provider");
+    }
+    if let Some(email) = rocket
+        .state::<GlobalConfig>()
+        .unwrap()
+        .borrow_email_config()
+    {
+        AuthProvider::find_or_create(email.get_name(), &db).expect("Failed to register provider");
+    }
+    let rotated =
+        Identity::rotate_ext_tokens(&cipher, &db).expect("Failed to rotate external tokens");
+    println!("Rotated {} external token(s)", rotated);
+    let migrated = Identity::migrate_legacy(rocket.state::<GlobalConfig>().unwrap(), &cipher, &db)
+        .expect("Failed to migrate legacy identities");
+    if migrated > 0 {
+        println!("Gave their external ID to {} legacy identities", migrated);
+    }
+    let hasher = rocket
+        .state::<GlobalConfig>()
+        .unwrap()
+        .borrow_security_config()
+        .get_token_hasher();
+    let hashed = Session::hash_legacy_tokens(&hasher, &db).expect("Failed to hash legacy tokens");
+    if hashed > 0 {
+        println!("Hashed {} legacy session token(s)", hashed);
+    }
+    drop(db);
+
+    println!("Launching the server ...");
+    rocket
+        .attach(cors)
         .mount("/", routes![index])
-        .mount(
-            "/login",
-            routes![
-                login::github::cb_login_github,
-                login::gitlab::cb_login_gitlab
-            ],
-        )
-        .mount("/api", routes![model::user::get_username])
+        .mount("/login", login_routes)
+        .mount("/api", api_routes)
+        .register(catchers![
+            error::bad_request,
+            error::unauthorized,
+            error::forbidden,
+            error::not_found,
+            error::unprocessable_entity,
+            error::internal_error
+        ])
         .launch();
 }
@@ -0,0 +1,303 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use model::audit_log::{AuditEvent, AuditRecord};
+use model::token::{token_prefix, TokenHasher};
+use model::user::APIUser;
+use rocket::State;
+use rocket_contrib::json::Json;
+use schema::access_tokens;
+use state::global_config::GlobalConfig;
+
+/// Maximal length of the name of a token, as stored in the database
+const MAX_NAME_LENGTH: usize = 50;
+
+/// What a request is allowed to do.
+/// Sessions are allowed everything, personal access tokens only what they have been granted
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// Download the puzzle inputs
+    #[serde(rename = "read:inputs")]
+    ReadInputs,
+    /// Submit answers
+    #[serde(rename = "submit")]
+    Submit,
+    /// Read the leaderboards
+    #[serde(rename = "read:leaderboards")]
+    ReadLeaderboards,
+    /// Read the username and the profile of the user
+    #[serde(rename = "read:profile")]
+    ReadProfile,
+}
+
+impl Scope {
+    /// Lists every scope
+    pub fn all() -> Vec<Scope> {
+        vec![
+            Scope::ReadInputs,
+            Scope::Submit,
+            Scope::ReadLeaderboards,
+            Scope::ReadProfile,
+        ]
+    }
+
+    /// Gets the name of the scope, as given by the users and stored in the database
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Scope::ReadInputs => "read:inputs",
+            Scope::Submit => "submit",
+            Scope::ReadLeaderboards => "read:leaderboards",
+            Scope::ReadProfile => "read:profile",
+        }
+    }
+
+    /// Finds a scope by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Scope::all().into_iter().find(|scope| scope.name() == name)
+    }
+}
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a personal access token, created by a user for its scripts.
+/// It is stored the same way as the tokens of the sessions, but never expires
+pub struct AccessToken {
+    /// The unique ID of the token
+    pub id: Option<i32>,
+    /// The ID of the `User` owning the token
+    pub user_id: i32,
+    /// The name given by the user, to tell its tokens apart
+    pub name: String,
+    /// The public part of the token
+    pub token_prefix: String,
+    /// The keyed hash of the token
+    pub token_hash: String,
+    /// The names of the scopes granted to the token, separated by spaces
+    pub scopes: String,
+    /// When the token has been created
+    pub created_at: NaiveDateTime,
+    /// Last time the token has been used to access the API, if ever
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl AccessToken {
+    /// Creates a new token for the given user, granted the given scopes.
+    /// Returns the token along with its secret value, which is only shown once
+    pub fn create(
+        user_id: i32,
+        name: &str,
+        scopes: &[Scope],
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(Self, String), ApiError> {
+        let scopes: Vec<&str> = scopes.iter().map(|scope| scope.name()).collect();
+        let (token, hashed_token) = hasher.generate();
+        diesel::insert_into(access_tokens::table)
+            .values((
+                access_tokens::user_id.eq(user_id),
+                access_tokens::name.eq(name),
+                access_tokens::token_prefix.eq(hashed_token.prefix),
+                access_tokens::token_hash.eq(hashed_token.hash),
+                access_tokens::scopes.eq(scopes.join(" ")),
+                access_tokens::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(db)?;
+
+        AccessToken::find_valid(&token, hasher, db)
+            .map(|access_token| (access_token, token))
+            .map_err(|_| ApiError::Internal(format!("Failed to create the access token")))
+    }
+
+    /// Finds the access token matching the given token
+    pub fn find_valid(
+        token: &str,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Self, ()> {
+        let prefix = token_prefix(token).ok_or(())?;
+        let access_token = access_tokens::table
+            .filter(access_tokens::token_prefix.eq(prefix))
+            .first::<Self>(db)
+            .map_err(|_| ())?;
+
+        if hasher.verify(token, &access_token.token_hash) {
+            Ok(access_token)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Lists the access tokens of the given user, from the newest to the oldest
+    pub fn list_for_user(
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<Self>, ApiError> {
+        access_tokens::table
+            .filter(access_tokens::user_id.eq(user_id))
+            .order(access_tokens::created_at.desc())
+            .load::<Self>(db)
+            .map_err(ApiError::from)
+    }
+
+    /// Revokes an access token of the given user.
+    /// Returns whether a token has been revoked
+    pub fn revoke(id: i32, user_id: i32, db: &diesel::SqliteConnection) -> Result<bool, ApiError> {
+        diesel::delete(
+            access_tokens::table
+                .filter(access_tokens::id.eq(id))
+                .filter(access_tokens::user_id.eq(user_id)),
+        )
+        .execute(db)
+        .map(|count| count > 0)
+        .map_err(ApiError::from)
+    }
+
+    /// Marks the token as being used right now
+    pub fn touch(&self, db: &diesel::SqliteConnection) -> Result<(), ApiError> {
+        diesel::update(access_tokens::table.filter(access_tokens::id.eq(self.id)))
+            .set(access_tokens::last_used_at.eq(Utc::now().naive_utc()))
+            .execute(db)
+            .map(|_| ())
+            .map_err(ApiError::from)
+    }
+
+    /// Gets the scopes granted to the token. Scopes that don't exist anymore are left out
+    pub fn get_scopes(&self) -> Vec<Scope> {
+        self.scopes
+            .split(' ')
+            .filter_map(Scope::from_name)
+            .collect()
+    }
+}
+
+/// Describes an access token as exposed by the API.
+/// The token itself is only sent back once, when it is created
+#[derive(Serialize, Debug)]
+pub struct APIAccessToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl APIAccessToken {
+    pub fn new_from_token(access_token: AccessToken, token: Option<String>) -> Self {
+        APIAccessToken {
+            id: access_token.id.unwrap(),
+            scopes: access_token.get_scopes(),
+            name: access_token.name,
+            created_at: access_token.created_at,
+            last_used_at: access_token.last_used_at,
+            token,
+        }
+    }
+}
+
+/// Name and scopes of a new access token
+#[derive(Deserialize)]
+pub struct NewAccessToken {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+/// Lists the access tokens of the user
+#[get("/me/tokens")]
+pub fn get_tokens(
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<Vec<APIAccessToken>>, ApiError> {
+    api_user.require_session()?;
+    let tokens = AccessToken::list_for_user(api_user.id, &db)?
+        .into_iter()
+        .map(|access_token| APIAccessToken::new_from_token(access_token, None))
+        .collect();
+    Ok(Json(tokens))
+}
+
+/// Creates an access token. Its value is only given back in this response
+#[post("/me/tokens", format = "json", data = "<new_token>")]
+pub fn create_token(
+    new_token: Json<NewAccessToken>,
+    api_user: APIUser,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+) -> Result<Json<APIAccessToken>, ApiError> {
+    // Tokens can't create other tokens, which would let them escape their scopes
+    api_user.require_session()?;
+
+    let name = new_token.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::Validation(format!(
+            "A token is named with 1 to {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if new_token.scopes.is_empty() {
+        return Err(ApiError::Validation(
+            "A token needs at least one scope".into(),
+        ));
+    }
+
+    let hasher = config.borrow_security_config().get_token_hasher();
+    let (access_token, token) =
+        AccessToken::create(api_user.id, name, &new_token.scopes, &hasher, &db)?;
+    AuditRecord::new(AuditEvent::TokenCreated)
+        .with_user(api_user.id)
+        .with_details(format!("{} ({})", access_token.name, access_token.scopes))
+        .record(&db);
+    Ok(Json(APIAccessToken::new_from_token(
+        access_token,
+        Some(token),
+    )))
+}
+
+/// Revokes one of the access tokens of the user
+#[delete("/me/tokens/<id>")]
+pub fn delete_token(id: i32, api_user: APIUser, db: DatabaseConn) -> Result<(), ApiError> {
+    api_user.require_session()?;
+    match AccessToken::revoke(id, api_user.id, &db)? {
+        true => {
+            AuditRecord::new(AuditEvent::TokenRevoked)
+                .with_user(api_user.id)
+                .with_details(format!("Token {}", id))
+                .record(&db);
+            Ok(())
+        }
+        false => Err(ApiError::NotFound),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{AccessToken, Scope};
+    use db::TestDatabase;
+    use model::token::TokenHasher;
+    use rocket::Rocket;
+
+    #[test]
+    pub fn create_and_revoke_access_token() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        let scopes = [Scope::ReadInputs, Scope::Submit];
+        let (access_token, token) =
+            AccessToken::create(1, "bot", &scopes, &hasher, &db).expect("Valid access token");
+        assert_eq!(access_token.get_scopes(), scopes.to_vec());
+        assert!(AccessToken::find_valid(&token, &hasher, &db).is_ok());
+
+        // Only the owner of the token can revoke it
+        assert_eq!(
+            AccessToken::revoke(access_token.id.unwrap(), 2, &db),
+            Ok(false)
+        );
+        assert_eq!(
+            AccessToken::revoke(access_token.id.unwrap(), 1, &db),
+            Ok(true)
+        );
+        assert!(AccessToken::find_valid(&token, &hasher, &db).is_err());
+    }
+}
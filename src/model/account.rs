@@ -0,0 +1,249 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use login::provider::OAuthProvider;
+use model::access_token::{APIAccessToken, AccessToken};
+use model::audit_log::{AuditEntry, AuditEvent, AuditRecord};
+use model::identity::{APIIdentity, Identity};
+use model::role::UserRole;
+use model::session::{APISession, Session};
+use model::user::{APIUser, Profile, User};
+use reqwest::Client;
+use rocket::http::{Cookie, Cookies};
+use rocket::State;
+use rocket_contrib::json::Json;
+use schema::{access_tokens, email_logins, identities, local_credentials, login_codes, sessions};
+use state::global_config::GlobalConfig;
+
+/// Everything tied to an account, as given to the user who asks for it
+#[derive(Serialize, Debug)]
+pub struct AccountExport {
+    pub exported_at: NaiveDateTime,
+    /// The profile of the user, along with its stars and its roles
+    pub profile: Profile,
+    pub identities: Vec<APIIdentity>,
+    pub sessions: Vec<APISession>,
+    pub access_tokens: Vec<APIAccessToken>,
+    /// The logins and the other events recorded about the user
+    pub events: Vec<AuditEntry>,
+}
+
+impl AccountExport {
+    /// Gathers everything tied to the given user
+    pub fn for_user(
+        user: User,
+        session_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Self, ApiError> {
+        let user_id = user.id.unwrap();
+        Ok(AccountExport {
+            exported_at: Utc::now().naive_utc(),
+            profile: Profile::for_user(user, db)?,
+            identities: Identity::list_for_user(user_id, db)?
+                .into_iter()
+                .map(|(identity, provider)| APIIdentity::new_from_identity(identity, provider))
+                .collect(),
+            sessions: Session::list_for_user(user_id, db)?
+                .into_iter()
+                .map(|session| APISession::new_from_session(session, session_id))
+                .collect(),
+            access_tokens: AccessToken::list_for_user(user_id, db)?
+                .into_iter()
+                .map(|access_token| APIAccessToken::new_from_token(access_token, None))
+                .collect(),
+            events: AuditEntry::list_for_user(user_id, db)?,
+        })
+    }
+}
+
+/// Erases everything tied to the given user, whose row is only anonymized.
+/// Returns its former identities, along with the name of their provider, as the tokens they
+/// hold still have to be revoked
+fn erase_account(
+    user: &mut User,
+    db: &diesel::SqliteConnection,
+) -> Result<Vec<(Identity, String)>, ApiError> {
+    let user_id = user.id.unwrap();
+
+    db.transaction(|| {
+        // The last admin can't leave, as no one could manage the users anymore
+        for role in UserRole::list_for_user(user_id, db)? {
+            UserRole::revoke(user_id, role, db)?;
+        }
+
+        let identities = Identity::list_for_user(user_id, db)?;
+        let identity_ids: Vec<i32> = identities
+            .iter()
+            .filter_map(|(identity, _)| identity.id)
+            .collect();
+        let ext_ids: Vec<&str> = identities
+            .iter()
+            .filter_map(|(identity, _)| identity.ext_id.as_ref().map(String::as_str))
+            .collect();
+
+        diesel::delete(
+            local_credentials::table.filter(local_credentials::identity_id.eq_any(&identity_ids)),
+        )
+        .execute(db)?;
+        // Pending login links are only tied to the addresses of the user
+        diesel::delete(email_logins::table.filter(email_logins::email.eq_any(&ext_ids)))
+            .execute(db)?;
+        diesel::delete(identities::table.filter(identities::user_id.eq(user_id))).execute(db)?;
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(db)?;
+        diesel::delete(access_tokens::table.filter(access_tokens::user_id.eq(user_id)))
+            .execute(db)?;
+        diesel::delete(login_codes::table.filter(login_codes::user_id.eq(user_id))).execute(db)?;
+        AuditEntry::anonymize_user(user_id, &ext_ids, db)?;
+        user.anonymize(db)?;
+
+        Ok(identities)
+    })
+}
+
+/// Revokes the tokens held by the given identities at their provider, when it allows it.
+/// The account is already gone, so failures are only logged
+fn revoke_provider_tokens(identities: &[(Identity, String)], config: &GlobalConfig) {
+    let cipher = match config.borrow_security_config().get_token_cipher() {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            println!("Failed to revoke the tokens of a deleted account: {}", e);
+            return;
+        }
+    };
+    let client = Client::new();
+
+    for (identity, provider) in identities {
+        // Identities that aren't backed by an OAUTH provider have no token
+        let provider: &dyn OAuthProvider = match config.find_provider(provider) {
+            Some(provider) => provider,
+            None => continue,
+        };
+
+        // Revocation authenticates with the credentials of our app rather than with the tokens
+        // of the user, so it doesn't go through a `ProviderClient`, which would also refresh
+        // an expired access token only for it to be revoked
+        let tokens = identity
+            .ext_token
+            .iter()
+            .chain(identity.ext_refresh_token.iter());
+        for token in tokens {
+            if let Err(e) = cipher
+                .open(token)
+                .and_then(|token| provider.revoke_token(&client, &token))
+            {
+                println!("Failed to revoke the tokens of a deleted account: {}", e);
+            }
+        }
+    }
+}
+
+/// Gives every piece of data tied to the account of the user, as a JSON archive
+#[get("/me/export")]
+pub fn export_account(
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<AccountExport>, ApiError> {
+    let session_id = api_user.require_session()?;
+    let user = User::find_by_id(api_user.id, &db).map_err(|_| ApiError::NotFound)?;
+    Ok(Json(AccountExport::for_user(user, session_id, &db)?))
+}
+
+/// Deletes the account of the user. Its data is erased, and its tokens are revoked at the
+/// providers that allow it. The user itself is kept as an anonymous placeholder, so that the
+/// leaderboards it appeared on stay consistent
+#[delete("/me")]
+pub fn delete_account(
+    api_user: APIUser,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+    mut cookies: Cookies,
+) -> Result<(), ApiError> {
+    api_user.require_session()?;
+    let mut user = User::find_by_id(api_user.id, &db).map_err(|_| ApiError::NotFound)?;
+
+    let identities = erase_account(&mut user, &db)?;
+    AuditRecord::new(AuditEvent::AccountDeleted)
+        .with_user(api_user.id)
+        .record(&db);
+    cookies.remove(Cookie::named("api_token"));
+
+    revoke_provider_tokens(&identities, &config);
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::erase_account;
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use error::ApiError;
+    use model::audit_log::{AuditEntry, AuditEvent, AuditRecord};
+    use model::auth_service::{AuthProvider, AuthService};
+    use model::identity::Identity;
+    use model::local_credential::LocalCredential;
+    use model::role::{Role, UserRole};
+    use model::session::Session;
+    use model::token::TokenHasher;
+    use model::user::User;
+    use rocket::Rocket;
+    use schema::{audit_log, sessions, user_roles};
+
+    #[test]
+    pub fn erase_user_data() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        // Everything is rolled back, so that the admins of previous runs don't get in the way
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(user_roles::table).execute(&**db)?;
+            let local = AuthProvider::find_or_create("local", &db)?;
+            let mut user = AuthService::new()
+                .with_ext_id("leaver".into())
+                .with_username("Leaver".into())
+                .with_auth_service_id(local)
+                .execute(&db)?;
+            let user_id = user.id.unwrap();
+            let identity = Identity::find_by_ext_id("leaver", local, &db).unwrap();
+            LocalCredential::create(identity.id.unwrap(), "correct horse", &db)?;
+            Session::create(user_id, Some("test_agent".into()), &hasher, &db)?;
+            AuditRecord::new(AuditEvent::Login)
+                .with_user(user_id)
+                .with_details("first login".into())
+                .record(&db);
+            AuditRecord::new(AuditEvent::LoginFailed)
+                .with_details("Leaver: invalid_credentials: Invalid username or password".into())
+                .record(&db);
+
+            // The last admin can't leave, and nothing is erased
+            UserRole::grant(user_id, Role::Admin, &db)?;
+            assert!(erase_account(&mut user, &db).is_err());
+            assert!(Identity::find_by_ext_id("leaver", local, &db).is_some());
+            diesel::delete(user_roles::table).execute(&**db)?;
+
+            assert_eq!(erase_account(&mut user, &db)?.len(), 1);
+            let anonymous = format!("Anonymous user #{}", user_id);
+            assert_eq!(user.username, anonymous);
+            assert_eq!(User::find_by_id(user_id, &db).unwrap().username, anonymous);
+            assert!(Identity::list_for_user(user_id, &db)?.is_empty());
+            assert!(LocalCredential::find_for_identity(identity.id.unwrap(), &db).is_none());
+            let sessions: i64 = sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .count()
+                .get_result(&**db)?;
+            assert_eq!(sessions, 0);
+
+            // The events are kept, without anything telling about the user
+            assert!(AuditEntry::list_for_user(user_id, &db)?
+                .iter()
+                .all(|entry| entry.ip.is_none() && entry.details.is_none()));
+            let failures: i64 = audit_log::table
+                .filter(audit_log::details.like("Leaver: %"))
+                .count()
+                .get_result(&**db)?;
+            assert_eq!(failures, 0);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,423 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use model::role::AdminUser;
+use model::session::UserAgent;
+use rocket::request::{FromRequest, Request};
+use rocket::Outcome;
+use rocket::State;
+use rocket_contrib::json::Json;
+use schema::audit_log;
+use state::global_config::GlobalConfig;
+
+/// Maximal number of entries given back at once
+const MAX_ENTRIES: i64 = 500;
+
+/// Format of the dates given to filter the log
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Something worth recording about the authentication of the users or their administration
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A user logged in, or registered
+    Login,
+    /// A login attempt failed
+    LoginFailed,
+    /// A user linked a new identity
+    IdentityLinked,
+    /// A user created a personal access token
+    TokenCreated,
+    /// A user revoked a personal access token
+    TokenRevoked,
+    /// An admin granted a role
+    RoleGranted,
+    /// An admin revoked a role
+    RoleRevoked,
+    /// An admin suspended a user
+    UserSuspended,
+    /// An admin lifted the suspension of a user
+    UserUnsuspended,
+    /// A user deleted its account
+    AccountDeleted,
+}
+
+impl AuditEvent {
+    /// Lists every event
+    pub fn all() -> Vec<AuditEvent> {
+        vec![
+            AuditEvent::Login,
+            AuditEvent::LoginFailed,
+            AuditEvent::IdentityLinked,
+            AuditEvent::TokenCreated,
+            AuditEvent::TokenRevoked,
+            AuditEvent::RoleGranted,
+            AuditEvent::RoleRevoked,
+            AuditEvent::UserSuspended,
+            AuditEvent::UserUnsuspended,
+            AuditEvent::AccountDeleted,
+        ]
+    }
+
+    /// Gets the name of the event, as stored in the database
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AuditEvent::Login => "login",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::IdentityLinked => "identity_linked",
+            AuditEvent::TokenCreated => "token_created",
+            AuditEvent::TokenRevoked => "token_revoked",
+            AuditEvent::RoleGranted => "role_granted",
+            AuditEvent::RoleRevoked => "role_revoked",
+            AuditEvent::UserSuspended => "user_suspended",
+            AuditEvent::UserUnsuspended => "user_unsuspended",
+            AuditEvent::AccountDeleted => "account_deleted",
+        }
+    }
+
+    /// Finds an event by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        AuditEvent::all()
+            .into_iter()
+            .find(|event| event.name() == name)
+    }
+}
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+/// Describes an entry of the audit log, as present in the database
+pub struct AuditEntry {
+    /// The unique ID of the entry
+    pub id: Option<i32>,
+    /// The name of the `AuditEvent`
+    pub event: String,
+    /// The ID of the `User` the event is about, if known
+    pub user_id: Option<i32>,
+    /// The ID of the `User` who caused the event, when it isn't the user itself
+    pub actor_id: Option<i32>,
+    /// The name of the `AuthProvider` involved, for logins
+    pub provider: Option<String>,
+    /// The IP address of the client, if known
+    pub ip: Option<String>,
+    /// The user agent of the client, if any
+    pub user_agent: Option<String>,
+    /// What happened, such as the reason of a failure
+    pub details: Option<String>,
+    /// When the event happened
+    pub created_at: NaiveDateTime,
+}
+
+/// Filters of the audit log, as given by an admin
+pub struct AuditFilter {
+    pub user_id: Option<i32>,
+    pub event: Option<AuditEvent>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl AuditEntry {
+    /// Lists every entry about the given user, from the oldest to the newest
+    pub fn list_for_user(
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<Self>, ApiError> {
+        audit_log::table
+            .filter(audit_log::user_id.eq(user_id))
+            .order(audit_log::created_at.asc())
+            .load::<Self>(db)
+            .map_err(ApiError::from)
+    }
+
+    /// Forgets where the events about the given user came from and what they told, once it
+    /// deleted its account. Failed logins with a local account are tied to the user when the
+    /// account existed at the time. Those recorded before it was registered aren't, but start with
+    /// the username that has been typed, which is one of the given external IDs
+    pub fn anonymize_user(
+        user_id: i32,
+        ext_ids: &[&str],
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        let anonymized = || {
+            (
+                audit_log::ip.eq(None::<String>),
+                audit_log::user_agent.eq(None::<String>),
+                audit_log::details.eq(None::<String>),
+            )
+        };
+        diesel::update(audit_log::table.filter(audit_log::user_id.eq(user_id)))
+            .set(anonymized())
+            .execute(db)?;
+
+        // Usernames are compared regardless of their case, as LIKE does
+        for ext_id in ext_ids {
+            let pattern = format!(
+                "{}: %",
+                ext_id
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            diesel::update(
+                audit_log::table
+                    .filter(audit_log::event.eq(AuditEvent::LoginFailed.name()))
+                    .filter(audit_log::user_id.is_null())
+                    .filter(audit_log::details.like(pattern).escape('\\')),
+            )
+            .set(anonymized())
+            .execute(db)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the entries matching the given filters, from the newest to the oldest
+    pub fn search(
+        filter: &AuditFilter,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<Self>, ApiError> {
+        let mut query = audit_log::table.into_boxed();
+        if let Some(user_id) = filter.user_id {
+            query = query.filter(audit_log::user_id.eq(user_id));
+        }
+        if let Some(event) = filter.event {
+            query = query.filter(audit_log::event.eq(event.name()));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(audit_log::created_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(audit_log::created_at.lt(until));
+        }
+
+        query
+            .order(audit_log::created_at.desc())
+            .limit(MAX_ENTRIES)
+            .load::<Self>(db)
+            .map_err(ApiError::from)
+    }
+}
+
+/// Builds an entry of the audit log, then records it
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct AuditRecord {
+    event: String,
+    user_id: Option<i32>,
+    actor_id: Option<i32>,
+    provider: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+impl AuditRecord {
+    /// Starts describing the given event
+    pub fn new(event: AuditEvent) -> Self {
+        AuditRecord {
+            event: event.name().into(),
+            user_id: None,
+            actor_id: None,
+            provider: None,
+            ip: None,
+            user_agent: None,
+            details: None,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Sets the user the event is about
+    pub fn with_user(self, user_id: i32) -> Self {
+        AuditRecord {
+            user_id: Some(user_id),
+            ..self
+        }
+    }
+
+    /// Sets the user who caused the event, such as an admin
+    pub fn with_actor(self, actor_id: i32) -> Self {
+        AuditRecord {
+            actor_id: Some(actor_id),
+            ..self
+        }
+    }
+
+    /// Sets the provider involved in a login
+    pub fn with_provider(self, provider: &str) -> Self {
+        AuditRecord {
+            provider: Some(provider.into()),
+            ..self
+        }
+    }
+
+    /// Sets the client that made the request
+    pub fn with_client(self, client_ip: &ClientIp, user_agent: &UserAgent) -> Self {
+        AuditRecord {
+            ip: client_ip.0.clone(),
+            user_agent: user_agent.0.clone(),
+            ..self
+        }
+    }
+
+    /// Describes what happened
+    pub fn with_details(self, details: String) -> Self {
+        AuditRecord {
+            details: Some(details),
+            ..self
+        }
+    }
+
+    /// Records the entry. Failing to do so is only logged, and doesn't prevent the request
+    pub fn record(self, db: &diesel::SqliteConnection) {
+        if let Err(e) = diesel::insert_into(audit_log::table)
+            .values(&self)
+            .execute(db)
+        {
+            println!("Failed to record the {} event: {}", self.event, e);
+        }
+    }
+}
+
+/// The IP address of the client that made a request, if known
+pub struct ClientIp(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> rocket::request::Outcome<Self, Self::Error> {
+        // Anyone can send a `X-Real-IP` header, so it is only read from the trusted proxies
+        let remote = request.remote().map(|address| address.ip());
+        let trusted = match (remote, request.guard::<State<GlobalConfig>>()) {
+            (Some(ip), Outcome::Success(config)) => {
+                config.borrow_security_config().is_trusted_proxy(&ip)
+            }
+            _ => false,
+        };
+        let ip = if trusted { request.client_ip() } else { remote };
+        Outcome::Success(ClientIp(ip.map(|ip| ip.to_string())))
+    }
+}
+
+/// Searches the audit log, newest entries first
+/// user: only the events about this user
+/// event: only the events of this type (e.g. `login_failed`)
+/// since / until: only the events in this time range, as `YYYY-MM-DDTHH:MM:SS` UTC dates
+#[get("/admin/audit?<user>&<event>&<since>&<until>")]
+pub fn get_audit_log(
+    user: Option<i32>,
+    event: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    _admin: AdminUser,
+    db: DatabaseConn,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    let event = match event {
+        Some(event) => Some(
+            AuditEvent::from_name(&event)
+                .ok_or(ApiError::Validation(format!("Unknown event {}", event)))?,
+        ),
+        None => None,
+    };
+    let filter = AuditFilter {
+        user_id: user,
+        event,
+        since: parse_date(since)?,
+        until: parse_date(until)?,
+    };
+    Ok(Json(AuditEntry::search(&filter, &db)?))
+}
+
+/// Parses a date bounding the search
+fn parse_date(date: Option<String>) -> Result<Option<NaiveDateTime>, ApiError> {
+    match date {
+        Some(date) => NaiveDateTime::parse_from_str(&date, DATE_FORMAT)
+            .map(Some)
+            .map_err(|_| ApiError::Validation(format!("Invalid date {}", date))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{AuditEntry, AuditEvent, AuditFilter, AuditRecord};
+    use chrono::{Duration, Utc};
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use error::ApiError;
+    use rocket::Rocket;
+    use schema::audit_log;
+
+    #[test]
+    pub fn record_and_search_events() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+
+        // Everything is rolled back, so that the entries of previous runs don't get in the way
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(audit_log::table).execute(&**db)?;
+            AuditRecord::new(AuditEvent::LoginFailed)
+                .with_provider("github")
+                .with_details("invalid_login: Invalid login state".into())
+                .record(&db);
+            AuditRecord::new(AuditEvent::Login)
+                .with_user(42)
+                .with_provider("github")
+                .record(&db);
+            AuditRecord::new(AuditEvent::RoleGranted)
+                .with_user(42)
+                .with_actor(7)
+                .record(&db);
+
+            // Entries recorded within the same instant come in any order
+            let search = |user_id, event, since, until| -> Result<Vec<_>, ApiError> {
+                let filter = AuditFilter {
+                    user_id,
+                    event,
+                    since,
+                    until,
+                };
+                let mut entries: Vec<(String, Option<i32>, Option<i32>)> =
+                    AuditEntry::search(&filter, &db)?
+                        .into_iter()
+                        .map(|entry| (entry.event, entry.user_id, entry.actor_id))
+                        .collect();
+                entries.sort();
+                Ok(entries)
+            };
+            let login = ("login".to_string(), Some(42), None);
+            let login_failed = ("login_failed".to_string(), None, None);
+            let role_granted = ("role_granted".to_string(), Some(42), Some(7));
+
+            assert_eq!(
+                search(None, None, None, None)?,
+                vec![login.clone(), login_failed.clone(), role_granted.clone()]
+            );
+            assert_eq!(
+                search(Some(42), None, None, None)?,
+                vec![login.clone(), role_granted.clone()]
+            );
+            assert_eq!(
+                search(Some(42), Some(AuditEvent::Login), None, None)?,
+                vec![login.clone()]
+            );
+            assert_eq!(
+                search(None, Some(AuditEvent::LoginFailed), None, None)?,
+                vec![login_failed.clone()]
+            );
+            assert_eq!(search(Some(7), None, None, None)?, vec![]);
+
+            let hour_ago = Utc::now().naive_utc() - Duration::hours(1);
+            let in_an_hour = Utc::now().naive_utc() + Duration::hours(1);
+            assert_eq!(
+                search(None, Some(AuditEvent::RoleGranted), Some(hour_ago), None)?,
+                vec![role_granted.clone()]
+            );
+            assert_eq!(search(None, None, Some(in_an_hour), None)?, vec![]);
+            assert_eq!(search(None, None, None, Some(hour_ago))?, vec![]);
+            assert_eq!(
+                search(None, None, Some(hour_ago), Some(in_an_hour))?,
+                vec![login, login_failed, role_granted]
+            );
+            Ok(())
+        });
+    }
+}
@@ -1,16 +1,58 @@
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use model::user::{InsertUser, User};
+use error::ApiError;
+use model::encrypted_token::EncryptedToken;
+use model::identity::{Identity, InsertIdentity};
+use model::role::UserRole;
+use model::user::User;
+use schema::authprovider;
+use state::admin_config::AdminConfig;
 
 #[derive(Queryable)]
-pub struct AuthProvider<'a> {
+pub struct AuthProvider {
     pub id: Option<i32>,
-    pub provider_name: &'a str,
+    pub provider_name: String,
+}
+
+impl AuthProvider {
+    /// Gets the ID of the `AuthProvider` with the given name, registering it if it doesn't exist.
+    /// Each configured provider instance (e.g. gitlab.com and a self-hosted Gitlab) gets its own
+    pub fn find_or_create(name: &str, db: &diesel::SqliteConnection) -> Result<i32, ApiError> {
+        if let Some(id) = AuthProvider::find_id(name, db) {
+            return Ok(id);
+        }
+
+        diesel::insert_into(authprovider::table)
+            .values(authprovider::prov_name.eq(name))
+            .execute(db)?;
+
+        AuthProvider::find_id(name, db).ok_or(ApiError::Internal(format!(
+            "Failed to register provider {}",
+            name
+        )))
+    }
+
+    /// Gets the ID of the `AuthProvider` with the given name
+    pub fn find_id(name: &str, db: &diesel::SqliteConnection) -> Option<i32> {
+        authprovider::table
+            .filter(authprovider::prov_name.eq(name))
+            .first::<AuthProvider>(db)
+            .ok()
+            .and_then(|provider| provider.id)
+    }
 }
 
 pub struct AuthService {
     username: Option<String>,
+    ext_id: Option<String>,
     id_auth_service: Option<i32>,
-    token: Option<String>,
+    token: Option<EncryptedToken>,
+    refresh_token: Option<EncryptedToken>,
+    token_expires_at: Option<NaiveDateTime>,
+    linked_user: Option<i32>,
+    avatar_url: Option<String>,
+    profile_url: Option<String>,
+    admin: Option<AdminConfig>,
 }
 
 impl AuthService {
@@ -18,49 +60,110 @@ impl AuthService {
     pub fn new() -> Self {
         AuthService {
             username: None,
+            ext_id: None,
             id_auth_service: None,
             token: None,
+            refresh_token: None,
+            token_expires_at: None,
+            linked_user: None,
+            avatar_url: None,
+            profile_url: None,
+            admin: None,
         }
     }
 
     /// Consumes the service to get the user information, or create it in the database
-    pub fn execute(self, db: &diesel::SqliteConnection) -> Result<User, String> {
-        use schema::users;
-
+    pub fn execute(self, db: &diesel::SqliteConnection) -> Result<User, ApiError> {
         // Extracts data from the service
-        let new_username: String = self.username.ok_or(format!("No username given"))?;
+        let new_username: String = self
+            .username
+            .ok_or(ApiError::Internal(format!("No username given")))?;
+        let new_ext_id: String = self
+            .ext_id
+            .ok_or(ApiError::Internal(format!("No external ID given")))?;
         let new_auth_service: i32 = self
             .id_auth_service
-            .ok_or(format!("No auth service given"))?;
-        let new_token: String = self.token.ok_or(format!("No token given"))?;
-
-        // Checks that the username/auth_provider combination isn't already existing in database,
-        // Which would mean that an user has already authenticated using this username
-        let existing_users: Option<Vec<User>> = users::table
-            .filter(users::username.eq(&new_username))
-            .filter(users::auth_provider.eq(&new_auth_service))
-            .load::<User>(db)
-            .ok();
-
-        match existing_users {
-            // Returns the existing user
-            Some(ref list) if list.len() >= 1 => {
-                let user: &User = list.first().unwrap();
-                Ok((*user).clone())
-            }
-            // Or create one :)
-            _ => {
-                let new_user = InsertUser::new(new_username, new_auth_service, new_token);
-                let inserted_user = diesel::insert_into(users::table)
-                    .values(&new_user)
-                    .execute(db);
-
-                match inserted_user {
-                    Ok(_) => Ok(User::new_from_inserted(new_user)),
-                    Err(e) => Err(format!("{}", e)),
+            .ok_or(ApiError::Internal(format!("No auth service given")))?;
+
+        // Checks that the ext_id/auth_provider combination isn't already existing in database,
+        // Which would mean that this identity has already been used, maybe under another username.
+        // Usernames can be taken over, so identities are never found by them
+        let existing_identity = Identity::find_by_ext_id(&new_ext_id, new_auth_service, db);
+
+        if let Some(mut identity) = existing_identity {
+            // An identity belongs to a single user
+            if let Some(linked_user) = self.linked_user {
+                if linked_user != identity.user_id {
+                    return Err(ApiError::Conflict(format!(
+                        "This account is already linked to another user"
+                    )));
                 }
             }
+
+            let mut user = User::find_by_id(identity.user_id, db)
+                .map_err(|_| ApiError::Internal(format!("No user found for this account")))?;
+            user.check_not_suspended()?;
+
+            // Keeps the username of the user in sync with the provider, unless it differs
+            if user.username == identity.username && user.username != new_username {
+                user.set_username(new_username.clone(), db)?;
+            }
+
+            // Returns the existing user, updating its identity with the tokens of this login
+            identity.set_identity(new_username, new_ext_id.clone(), db)?;
+            identity.set_profile(self.avatar_url, self.profile_url, db)?;
+            if let Some(token) = self.token {
+                identity.set_ext_tokens(token, self.refresh_token, self.token_expires_at, db)?;
+            }
+            if let Some(ref admin) = self.admin {
+                UserRole::bootstrap_admin(
+                    admin,
+                    new_auth_service,
+                    &new_ext_id,
+                    user.id.unwrap(),
+                    db,
+                )?;
+            }
+            return Ok(user);
+        }
+
+        // Or link the identity to the logged in user, or to a brand new one :)
+        // A new user is only kept along with its identity
+        let linked_user = self.linked_user;
+        let mut new_identity = InsertIdentity {
+            avatar_url: self.avatar_url,
+            profile_url: self.profile_url,
+            ..InsertIdentity::new(
+                0,
+                new_auth_service,
+                new_ext_id.clone(),
+                new_username.clone(),
+                self.token,
+                self.refresh_token,
+                self.token_expires_at,
+            )
+        };
+        let user = db.transaction::<_, ApiError, _>(|| {
+            let user = match linked_user {
+                Some(linked_user) => {
+                    if Identity::find_for_user(linked_user, new_auth_service, db).is_some() {
+                        return Err(ApiError::Conflict(format!(
+                            "Another account of this provider is already linked"
+                        )));
+                    }
+                    User::find_by_id(linked_user, db).map_err(|_| ApiError::NotFound)?
+                }
+                None => User::create(new_username, db)?,
+            };
+            new_identity.user_id = user.id.unwrap();
+            new_identity.insert(db)?;
+            Ok(user)
+        })?;
+
+        if let Some(ref admin) = self.admin {
+            UserRole::bootstrap_admin(admin, new_auth_service, &new_ext_id, user.id.unwrap(), db)?;
         }
+        Ok(user)
     }
 
     /// Supposed to return the ID of the `AuthProvider`
@@ -71,14 +174,31 @@ impl AuthService {
         }
     }
 
-    /// Sets the external token of the user being created
-    pub fn with_token(self, token: String) -> Self {
+    /// Sets the external token of the user being created, once encrypted.
+    /// Identities that aren't backed by an OAUTH provider have none
+    pub fn with_token(self, token: EncryptedToken) -> Self {
         AuthService {
             token: Some(token),
             ..self
         }
     }
 
+    /// Sets the token used to refresh the external token, once encrypted
+    pub fn with_refresh_token(self, refresh_token: EncryptedToken) -> Self {
+        AuthService {
+            refresh_token: Some(refresh_token),
+            ..self
+        }
+    }
+
+    /// Sets the date after which the external token expires
+    pub fn with_token_expiry(self, expires_at: NaiveDateTime) -> Self {
+        AuthService {
+            token_expires_at: Some(expires_at),
+            ..self
+        }
+    }
+
     /// Specifies the username of the user to create / authenticate
     pub fn with_username(self, username: String) -> Self {
         AuthService {
@@ -86,6 +206,43 @@ impl AuthService {
             ..self
         }
     }
+
+    /// Sets the address of the avatar of the user on the `AuthProvider`, if it has one
+    pub fn with_avatar_url(self, avatar_url: Option<String>) -> Self {
+        AuthService { avatar_url, ..self }
+    }
+
+    /// Sets the address of the profile page of the user on the `AuthProvider`, if any
+    pub fn with_profile_url(self, profile_url: Option<String>) -> Self {
+        AuthService {
+            profile_url,
+            ..self
+        }
+    }
+
+    /// Links the identity to the given, already logged in, user instead of logging in
+    pub fn with_linked_user(self, user_id: i32) -> Self {
+        AuthService {
+            linked_user: Some(user_id),
+            ..self
+        }
+    }
+
+    /// Makes the user the first admin if it is the configured one, as long as there is no admin
+    pub fn with_admin_config(self, admin: Option<&AdminConfig>) -> Self {
+        AuthService {
+            admin: admin.cloned(),
+            ..self
+        }
+    }
+
+    /// Specifies the immutable ID of the user on the `AuthProvider`
+    pub fn with_ext_id(self, ext_id: String) -> Self {
+        AuthService {
+            ext_id: Some(ext_id),
+            ..self
+        }
+    }
 }
 
 #[cfg(test)]
@@ -93,6 +250,8 @@ pub mod tests {
     use super::AuthService;
     use super::User;
     use db::TestDatabase;
+    use error::ApiError;
+    use model::encrypted_token::TokenCipher;
     use rocket::http::Status;
     use rocket::local::Client;
     use rocket::Rocket;
@@ -104,15 +263,19 @@ pub mod tests {
         ext_token: String,
         db: TestDatabase,
     ) -> String {
-        let user: Result<User, String> = AuthService::new()
-            .with_username(username)
-            .with_auth_service_id(auth_provider)
-            .with_token(ext_token)
-            .execute(&db);
+        let cipher = TokenCipher::new(1, vec![(1, vec![0; 32])]).expect("Valid cipher");
+        let user: Result<User, ApiError> = cipher.seal(&ext_token).and_then(|ext_token| {
+            AuthService::new()
+                .with_ext_id(format!("ext_{}", username))
+                .with_username(username)
+                .with_auth_service_id(auth_provider)
+                .with_token(ext_token)
+                .execute(&db)
+        });
 
         match user {
             Ok(_) => "Successfully logged in".into(),
-            Err(e) => e.into(),
+            Err(e) => e.to_string(),
         }
     }
 
@@ -147,5 +310,4 @@ pub mod tests {
 
         // TODO: test - Total number of user should still be one
     }
-
 }
@@ -0,0 +1,131 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use error::ApiError;
+use model::token::{token_prefix, TokenHasher};
+use schema::email_logins;
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a passwordless login, waiting for the user to follow the link mailed to them.
+/// The link carries a token that is stored the same way as the tokens of the sessions
+pub struct EmailLogin {
+    /// The unique ID of the login
+    pub id: Option<i32>,
+    /// The address the link has been sent to
+    pub email: String,
+    /// The public part of the token of the link
+    pub token_prefix: String,
+    /// The keyed hash of the token of the link
+    pub token_hash: String,
+    /// When the link has been sent
+    pub created_at: NaiveDateTime,
+    /// The link can't be used after this date
+    pub expires_at: NaiveDateTime,
+    /// The keyed hash of the nonce held by the browser that asked for the link
+    pub nonce_hash: String,
+}
+
+impl EmailLogin {
+    /// Starts a new passwordless login for the given address, valid for the given number of
+    /// minutes, from the browser holding the given nonce. Returns the token to send to the user
+    pub fn create(
+        email: &str,
+        lifetime_minutes: i64,
+        nonce: &str,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<String, ApiError> {
+        // Forgotten links are cleaned up along the way
+        let now = Utc::now().naive_utc();
+        diesel::delete(email_logins::table.filter(email_logins::expires_at.le(now))).execute(db)?;
+
+        let (token, hashed_token) = hasher.generate();
+        diesel::insert_into(email_logins::table)
+            .values((
+                email_logins::email.eq(email),
+                email_logins::token_prefix.eq(hashed_token.prefix),
+                email_logins::token_hash.eq(hashed_token.hash),
+                email_logins::created_at.eq(now),
+                email_logins::expires_at.eq(now + Duration::minutes(lifetime_minutes)),
+                email_logins::nonce_hash.eq(hasher.hash(nonce)),
+            ))
+            .execute(db)?;
+
+        Ok(token)
+    }
+
+    /// Counts the links sent to the given address that can still be used
+    pub fn count_pending(email: &str, db: &diesel::SqliteConnection) -> Result<i64, ApiError> {
+        email_logins::table
+            .filter(email_logins::email.eq(email))
+            .filter(email_logins::expires_at.gt(Utc::now().naive_utc()))
+            .count()
+            .get_result(db)
+            .map_err(ApiError::from)
+    }
+
+    /// Uses the login matching the given token, as long as it has not expired and the given
+    /// nonce is the one of the browser that asked for it.
+    /// A login can only be used once. Returns the address it has been sent to
+    pub fn consume(
+        token: &str,
+        nonce: &str,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<String, ()> {
+        let prefix = token_prefix(token).ok_or(())?;
+        let login = email_logins::table
+            .filter(email_logins::token_prefix.eq(prefix))
+            .filter(email_logins::expires_at.gt(Utc::now().naive_utc()))
+            .first::<Self>(db)
+            .map_err(|_| ())?;
+
+        // A forwarded link is left usable by the browser that asked for it
+        if !hasher.verify(nonce, &login.nonce_hash) {
+            return Err(());
+        }
+        hasher.consume_once(token, &login.token_hash, || {
+            diesel::delete(email_logins::table.filter(email_logins::id.eq(login.id))).execute(db)
+        })?;
+
+        Ok(login.email)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::EmailLogin;
+    use db::TestDatabase;
+    use model::token::TokenHasher;
+    use rocket::Rocket;
+
+    #[test]
+    pub fn consume_email_login_once() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        let token =
+            EmailLogin::create("user@example.com", 15, "nonce", &hasher, &db).expect("Valid login");
+
+        // Only the browser that asked for the link can use it
+        assert_eq!(
+            EmailLogin::consume(&token, "other_nonce", &hasher, &db),
+            Err(())
+        );
+        assert_eq!(
+            EmailLogin::consume(&token, "nonce", &hasher, &db),
+            Ok("user@example.com".into())
+        );
+
+        // The link can't be followed twice
+        assert_eq!(EmailLogin::consume(&token, "nonce", &hasher, &db), Err(()));
+
+        // Only the links that can still be used are counted
+        let email = "pending@example.com";
+        let before = EmailLogin::count_pending(email, &db).expect("Valid count");
+        let token = EmailLogin::create(email, 15, "nonce", &hasher, &db).expect("Valid login");
+        assert_eq!(EmailLogin::count_pending(email, &db), Ok(before + 1));
+        EmailLogin::consume(&token, "nonce", &hasher, &db).expect("Valid login");
+        assert_eq!(EmailLogin::count_pending(email, &db), Ok(before));
+    }
+}
@@ -0,0 +1,172 @@
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use error::ApiError;
+use ring::aead;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::io::Write;
+
+/// Version of the format of the encrypted tokens
+const FORMAT_VERSION: &str = "v1";
+
+/// Length of the keys used to encrypt the tokens
+pub const KEY_LENGTH: usize = 32;
+
+/// An access token of an external provider, encrypted with one of the keys of the platform.
+///
+/// It is stored as `v1:<key id>:<base64 of nonce and ciphertext>`, and can only be read
+/// through a `TokenCipher`. It doesn't implement `Serialize` on purpose, so that it
+/// can never end up in a response.
+#[derive(AsExpression, FromSqlRow, Clone, PartialEq)]
+#[sql_type = "Text"]
+pub struct EncryptedToken(String);
+
+impl EncryptedToken {
+    /// Gets the ID of the key that encrypted this token, if it has been encrypted at all.
+    /// Tokens stored before encryption was introduced have none
+    pub fn key_id(&self) -> Option<u32> {
+        let mut parts = self.0.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(FORMAT_VERSION), Some(key_id), Some(_)) => key_id.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for EncryptedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptedToken(..)")
+    }
+}
+
+impl ToSql<Text, Sqlite> for EncryptedToken {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        <String as ToSql<Text, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for EncryptedToken {
+    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        <String as FromSql<Text, Sqlite>>::from_sql(bytes).map(EncryptedToken)
+    }
+}
+
+/// Encrypts and decrypts the access tokens of the external providers.
+///
+/// Several keys can be known at once : new tokens are always encrypted with the current key,
+/// while older keys are kept to read the tokens they encrypted until those get rotated.
+pub struct TokenCipher {
+    /// ID of the key used to encrypt new tokens
+    current_key: u32,
+    /// Every known key, by ID
+    keys: Vec<(u32, Vec<u8>)>,
+}
+
+impl TokenCipher {
+    /// Creates a new cipher, checking that every key is valid and that the current key is known
+    pub fn new(current_key: u32, keys: Vec<(u32, Vec<u8>)>) -> Result<Self, String> {
+        if let Some(&(id, _)) = keys.iter().find(|&&(_, ref key)| key.len() != KEY_LENGTH) {
+            return Err(format!("Key {} should be {} bytes long", id, KEY_LENGTH));
+        }
+
+        if !keys.iter().any(|&(id, _)| id == current_key) {
+            return Err(format!("Unknown current key {}", current_key));
+        }
+
+        Ok(TokenCipher { current_key, keys })
+    }
+
+    /// Encrypts a token with the current key
+    pub fn seal(&self, token: &str) -> Result<EncryptedToken, ApiError> {
+        let error = || ApiError::Internal(format!("Failed to encrypt the token"));
+        let key = aead::SealingKey::new(&aead::CHACHA20_POLY1305, self.key(self.current_key)?)
+            .map_err(|_| error())?;
+
+        let mut nonce = vec![0; aead::CHACHA20_POLY1305.nonce_len()];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| error())?;
+
+        let tag_len = aead::CHACHA20_POLY1305.tag_len();
+        let mut in_out = token.as_bytes().to_vec();
+        in_out.extend(vec![0; tag_len]);
+        let out_len =
+            aead::seal_in_place(&key, &nonce, &[], &mut in_out, tag_len).map_err(|_| error())?;
+
+        nonce.extend_from_slice(&in_out[..out_len]);
+        Ok(EncryptedToken(format!(
+            "{}:{}:{}",
+            FORMAT_VERSION,
+            self.current_key,
+            base64::encode(&nonce)
+        )))
+    }
+
+    /// Decrypts a token with the key that encrypted it
+    pub fn open(&self, token: &EncryptedToken) -> Result<String, ApiError> {
+        let error = || ApiError::Internal(format!("Failed to decrypt the token"));
+        let key_id = token
+            .key_id()
+            .ok_or(ApiError::Internal(format!("The token is not encrypted")))?;
+        let key = aead::OpeningKey::new(&aead::CHACHA20_POLY1305, self.key(key_id)?)
+            .map_err(|_| error())?;
+
+        let payload = token.0.splitn(3, ':').nth(2).ok_or(error())?;
+        let mut data = base64::decode(payload).map_err(|_| error())?;
+        let nonce_len = aead::CHACHA20_POLY1305.nonce_len();
+        if data.len() < nonce_len {
+            return Err(error());
+        }
+
+        let mut in_out = data.split_off(nonce_len);
+        let plain = aead::open_in_place(&key, &data, &[], 0, &mut in_out).map_err(|_| error())?;
+        String::from_utf8(plain.to_vec()).map_err(|_| error())
+    }
+
+    /// Encrypts again, with the current key, a token that has been encrypted with an older key.
+    /// Tokens stored in plain text before encryption was introduced are encrypted as well.
+    /// Returns `None` if the token is already encrypted with the current key
+    pub fn rotate(&self, token: &EncryptedToken) -> Result<Option<EncryptedToken>, ApiError> {
+        match token.key_id() {
+            Some(key_id) if key_id == self.current_key => Ok(None),
+            Some(_) => self.seal(&self.open(token)?).map(Some),
+            None => self.seal(&token.0).map(Some),
+        }
+    }
+
+    /// Gets the key with the given ID
+    fn key(&self, id: u32) -> Result<&[u8], ApiError> {
+        self.keys
+            .iter()
+            .find(|&&(key_id, _)| key_id == id)
+            .map(|&(_, ref key)| key.as_slice())
+            .ok_or(ApiError::Internal(format!("Unknown key {}", id)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::TokenCipher;
+
+    #[test]
+    pub fn seal_open_and_rotate() {
+        let old_cipher = TokenCipher::new(1, vec![(1, vec![1; 32])]).expect("Valid cipher");
+        let sealed = old_cipher.seal("ext_token").expect("Sealed token");
+        assert_eq!(sealed.key_id(), Some(1));
+        assert_eq!(old_cipher.open(&sealed), Ok("ext_token".into()));
+
+        // A new key is introduced, the old one is kept to read existing tokens
+        let cipher =
+            TokenCipher::new(2, vec![(1, vec![1; 32]), (2, vec![2; 32])]).expect("Valid cipher");
+        assert_eq!(cipher.open(&sealed), Ok("ext_token".into()));
+
+        let rotated = cipher
+            .rotate(&sealed)
+            .expect("Rotated token")
+            .expect("New token");
+        assert_eq!(rotated.key_id(), Some(2));
+        assert_eq!(cipher.open(&rotated), Ok("ext_token".into()));
+        assert_eq!(cipher.rotate(&rotated), Ok(None));
+    }
+}
@@ -0,0 +1,426 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use login::provider_client::ProviderClient;
+use model::encrypted_token::{EncryptedToken, TokenCipher};
+use model::user::APIUser;
+use rocket::State;
+use rocket_contrib::json::Json;
+use schema::{authprovider, identities, local_credentials};
+use state::global_config::GlobalConfig;
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes an identity of a user on an external authentication provider.
+/// A user can log in with any of its identities
+pub struct Identity {
+    /// The unique ID of the identity
+    pub id: Option<i32>,
+    /// The ID of the `User` owning the identity
+    pub user_id: i32,
+    /// The ID of the external authentication provider (as referenced)
+    /// in the `AuthProvider` struct
+    pub auth_provider: i32,
+    /// The immutable ID of the user on the `AuthProvider`.
+    /// Identities created before it was introduced get it at startup, from their provider
+    pub ext_id: Option<String>,
+    /// The username of the user on the `AuthProvider`
+    pub username: String,
+    /// The *external* token, that allows to communicate with the
+    /// public API of an `AuthProvider`. Stored encrypted.
+    /// Identities that aren't backed by an OAUTH provider, such as local ones, have none
+    pub ext_token: Option<EncryptedToken>,
+    /// The token that allows to get a new *external* token once it expires, if the
+    /// `AuthProvider` uses them. Stored encrypted
+    pub ext_refresh_token: Option<EncryptedToken>,
+    /// When the *external* token expires, if it does
+    pub ext_token_expires_at: Option<NaiveDateTime>,
+    /// When the identity has been linked to the user
+    pub created_at: NaiveDateTime,
+    /// Address of the avatar of the user on the `AuthProvider`, as given at the last login
+    pub avatar_url: Option<String>,
+    /// Address of the profile page of the user on the `AuthProvider`, as given at the last login
+    pub profile_url: Option<String>,
+}
+
+impl Identity {
+    /// Finds the identity with the given external ID on the given auth provider
+    pub fn find_by_ext_id(
+        ext_id: &str,
+        auth_provider: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Option<Self> {
+        identities::table
+            .filter(identities::ext_id.eq(ext_id))
+            .filter(identities::auth_provider.eq(auth_provider))
+            .first::<Self>(db)
+            .ok()
+    }
+
+    /// Finds the identity of the given user on the given auth provider
+    pub fn find_for_user(
+        user_id: i32,
+        auth_provider: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Option<Self> {
+        identities::table
+            .filter(identities::user_id.eq(user_id))
+            .filter(identities::auth_provider.eq(auth_provider))
+            .first::<Self>(db)
+            .ok()
+    }
+
+    /// Lists the identities of the given user, along with the name of their provider
+    pub fn list_for_user(
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<(Self, String)>, ApiError> {
+        identities::table
+            .inner_join(authprovider::table)
+            .filter(identities::user_id.eq(user_id))
+            .select((identities::all_columns, authprovider::prov_name))
+            .order(identities::created_at.asc())
+            .load::<(Self, String)>(db)
+            .map_err(ApiError::from)
+    }
+
+    /// Unlinks an identity from the given user.
+    /// A user can't unlink its last identity, as it couldn't log in anymore.
+    /// Returns whether an identity has been unlinked
+    pub fn unlink(id: i32, user_id: i32, db: &diesel::SqliteConnection) -> Result<bool, ApiError> {
+        let count: i64 = identities::table
+            .filter(identities::user_id.eq(user_id))
+            .count()
+            .get_result(db)?;
+
+        if count <= 1 {
+            return Err(ApiError::Conflict(format!(
+                "The last identity of a user can't be unlinked"
+            )));
+        }
+
+        // The password of a local identity goes along with it
+        db.transaction(|| {
+            let identity_ids = identities::table
+                .filter(identities::id.eq(id))
+                .filter(identities::user_id.eq(user_id))
+                .select(identities::id);
+            diesel::delete(
+                local_credentials::table.filter(
+                    local_credentials::identity_id
+                        .nullable()
+                        .eq_any(identity_ids),
+                ),
+            )
+            .execute(db)?;
+
+            diesel::delete(
+                identities::table
+                    .filter(identities::id.eq(id))
+                    .filter(identities::user_id.eq(user_id)),
+            )
+            .execute(db)
+            .map(|count| count > 0)
+        })
+        .map_err(ApiError::from)
+    }
+
+    /// Updates the identity of the user on its `AuthProvider`, as the username may have changed
+    pub fn set_identity(
+        &mut self,
+        username: String,
+        ext_id: String,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::update(identities::table.filter(identities::id.eq(self.id)))
+            .set((
+                identities::username.eq(&username),
+                identities::ext_id.eq(&ext_id),
+            ))
+            .execute(db)?;
+
+        self.username = username;
+        self.ext_id = Some(ext_id);
+        Ok(())
+    }
+
+    /// Updates the avatar and the profile page of the user on its `AuthProvider`
+    pub fn set_profile(
+        &mut self,
+        avatar_url: Option<String>,
+        profile_url: Option<String>,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::update(identities::table.filter(identities::id.eq(self.id)))
+            .set((
+                identities::avatar_url.eq(&avatar_url),
+                identities::profile_url.eq(&profile_url),
+            ))
+            .execute(db)?;
+
+        self.avatar_url = avatar_url;
+        self.profile_url = profile_url;
+        Ok(())
+    }
+
+    /// Replaces the *external* tokens of the identity, after a new login or a refresh
+    pub fn set_ext_tokens(
+        &mut self,
+        ext_token: EncryptedToken,
+        ext_refresh_token: Option<EncryptedToken>,
+        ext_token_expires_at: Option<NaiveDateTime>,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::update(identities::table.filter(identities::id.eq(self.id)))
+            .set((
+                identities::ext_token.eq(&ext_token),
+                identities::ext_refresh_token.eq(&ext_refresh_token),
+                identities::ext_token_expires_at.eq(ext_token_expires_at),
+            ))
+            .execute(db)?;
+
+        self.ext_token = Some(ext_token);
+        self.ext_refresh_token = ext_refresh_token;
+        self.ext_token_expires_at = ext_token_expires_at;
+        Ok(())
+    }
+
+    /// Encrypts again, with the current key, every external token that has been encrypted with
+    /// an older key or stored in plain text. Returns the number of updated identities
+    pub fn rotate_ext_tokens(
+        cipher: &TokenCipher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<usize, ApiError> {
+        let identities = identities::table.load::<Self>(db)?;
+
+        let mut rotated = 0;
+        for identity in identities {
+            if let Some(ref ext_token) = identity.ext_token {
+                if let Some(ext_token) = cipher.rotate(ext_token)? {
+                    diesel::update(identities::table.filter(identities::id.eq(identity.id)))
+                        .set(identities::ext_token.eq(ext_token))
+                        .execute(db)?;
+                    rotated += 1;
+                }
+            }
+
+            if let Some(ref refresh_token) = identity.ext_refresh_token {
+                if let Some(refresh_token) = cipher.rotate(refresh_token)? {
+                    diesel::update(identities::table.filter(identities::id.eq(identity.id)))
+                        .set(identities::ext_refresh_token.eq(refresh_token))
+                        .execute(db)?;
+                }
+            }
+        }
+
+        Ok(rotated)
+    }
+
+    /// Gives their external ID to the identities created before it was introduced, by asking
+    /// their provider who owns their stored access token. Their username can't be trusted, as
+    /// anyone can take it over on the provider. Identities whose token doesn't work anymore
+    /// are left as they are, and can't be logged in with. Returns the number of migrated
+    /// identities
+    pub fn migrate_legacy(
+        config: &GlobalConfig,
+        cipher: &TokenCipher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<usize, ApiError> {
+        let legacy = identities::table
+            .inner_join(authprovider::table)
+            .filter(identities::ext_id.is_null())
+            .select((identities::all_columns, authprovider::prov_name))
+            .load::<(Self, String)>(db)?;
+
+        let mut migrated = 0;
+        for (mut identity, provider) in legacy {
+            let provider = match config.find_provider(&provider) {
+                Some(provider) => provider,
+                None => continue,
+            };
+            let user = ProviderClient::for_identity(&mut identity, provider, cipher, db)
+                .and_then(|client| provider.fetch_user(&client));
+            let ext_id = match user {
+                Ok(user) => user.ext_id,
+                Err(e) => {
+                    println!("Failed to migrate identity {}: {}", identity.id.unwrap(), e);
+                    continue;
+                }
+            };
+
+            // The user may have logged in again since, under a brand new identity
+            if Identity::find_by_ext_id(&ext_id, identity.auth_provider, db).is_some() {
+                println!(
+                    "Identity {} has been replaced by another",
+                    identity.id.unwrap()
+                );
+                continue;
+            }
+            diesel::update(identities::table.filter(identities::id.eq(identity.id)))
+                .set(identities::ext_id.eq(ext_id))
+                .execute(db)?;
+            migrated += 1;
+        }
+
+        Ok(migrated)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "identities"]
+pub struct InsertIdentity {
+    /// The ID of the `User` owning the identity
+    pub user_id: i32,
+    /// The ID of the `AuthProvider` that provides the authentication proof of the user
+    pub auth_provider: i32,
+    /// The immutable ID of the user on the `AuthProvider`
+    pub ext_id: String,
+    /// The username of the user on the `AuthProvider`
+    pub username: String,
+    /// The *external* token of the user to communicate with the public API of said service, if any
+    pub ext_token: Option<EncryptedToken>,
+    /// The token used to refresh the *external* token, if any
+    pub ext_refresh_token: Option<EncryptedToken>,
+    /// When the *external* token expires, if it does
+    pub ext_token_expires_at: Option<NaiveDateTime>,
+    /// When the identity has been linked to the user
+    pub created_at: NaiveDateTime,
+    /// Address of the avatar of the user on the `AuthProvider`, if any
+    pub avatar_url: Option<String>,
+    /// Address of the profile page of the user on the `AuthProvider`, if any
+    pub profile_url: Option<String>,
+}
+
+impl InsertIdentity {
+    /// Creates a new instance of `InsertIdentity`, that Diesel will use to link an identity
+    pub fn new(
+        user_id: i32,
+        auth_provider: i32,
+        ext_id: String,
+        username: String,
+        ext_token: Option<EncryptedToken>,
+        ext_refresh_token: Option<EncryptedToken>,
+        ext_token_expires_at: Option<NaiveDateTime>,
+    ) -> Self {
+        InsertIdentity {
+            user_id,
+            auth_provider,
+            ext_id,
+            username,
+            ext_token,
+            ext_refresh_token,
+            ext_token_expires_at,
+            created_at: Utc::now().naive_utc(),
+            avatar_url: None,
+            profile_url: None,
+        }
+    }
+
+    /// Links the identity to its user
+    pub fn insert(&self, db: &diesel::SqliteConnection) -> Result<Identity, ApiError> {
+        diesel::insert_into(identities::table)
+            .values(self)
+            .execute(db)?;
+
+        Identity::find_by_ext_id(&self.ext_id, self.auth_provider, db)
+            .ok_or(ApiError::Internal(format!("Failed to link the identity")))
+    }
+}
+
+/// Describes an identity as exposed by the API. Tokens are never sent back
+#[derive(Serialize, Debug)]
+pub struct APIIdentity {
+    pub id: i32,
+    /// Name of the provider of the identity
+    pub provider: String,
+    /// Username of the user on the provider
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub profile_url: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl APIIdentity {
+    pub fn new_from_identity(identity: Identity, provider: String) -> Self {
+        APIIdentity {
+            id: identity.id.unwrap(),
+            provider,
+            username: identity.username,
+            avatar_url: identity.avatar_url,
+            profile_url: identity.profile_url,
+            created_at: identity.created_at,
+        }
+    }
+}
+
+/// Lists the identities linked to the user
+#[get("/me/identities")]
+pub fn get_identities(
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<Vec<APIIdentity>>, ApiError> {
+    api_user.require_session()?;
+    let identities = Identity::list_for_user(api_user.id, &db)?
+        .into_iter()
+        .map(|(identity, provider)| APIIdentity::new_from_identity(identity, provider))
+        .collect();
+    Ok(Json(identities))
+}
+
+/// Unlinks one of the identities of the user, who won't be able to log in with it anymore
+#[delete("/me/identities/<id>")]
+pub fn delete_identity(id: i32, api_user: APIUser, db: DatabaseConn) -> Result<(), ApiError> {
+    api_user.require_session()?;
+    match Identity::unlink(id, api_user.id, &db)? {
+        true => Ok(()),
+        false => Err(ApiError::NotFound),
+    }
+}
+
+/// Updates one of the identities of the user from its provider, through its stored tokens, as
+/// the username, the avatar or the profile page may have changed since the last login
+#[post("/me/identities/<id>/refresh")]
+pub fn refresh_identity(
+    id: i32,
+    api_user: APIUser,
+    config: State<GlobalConfig>,
+    db: DatabaseConn,
+) -> Result<Json<APIIdentity>, ApiError> {
+    api_user.require_session()?;
+    let (mut identity, provider_name) = Identity::list_for_user(api_user.id, &db)?
+        .into_iter()
+        .find(|&(ref identity, _)| identity.id == Some(id))
+        .ok_or(ApiError::NotFound)?;
+
+    // Identities that aren't backed by an OAUTH provider have nothing to be refreshed from
+    let provider = config.find_provider(&provider_name).ok_or_else(|| {
+        ApiError::Conflict(format!(
+            "Identities of {} can't be refreshed",
+            provider_name
+        ))
+    })?;
+    let cipher = config
+        .borrow_security_config()
+        .get_token_cipher()
+        .map_err(ApiError::Internal)?;
+
+    let client = ProviderClient::for_identity(&mut identity, provider, &cipher, &db)?;
+    let user = provider.fetch_user(&client)?;
+    if identity
+        .ext_id
+        .as_ref()
+        .map_or(false, |ext_id| *ext_id != user.ext_id)
+    {
+        return Err(ApiError::Provider(format!(
+            "{} described another user",
+            provider.name()
+        )));
+    }
+
+    identity.set_identity(user.username, user.ext_id, &db)?;
+    identity.set_profile(user.avatar_url, user.profile_url, &db)?;
+    Ok(Json(APIIdentity::new_from_identity(
+        identity,
+        provider_name,
+    )))
+}
@@ -0,0 +1,121 @@
+use argon2::{self, Config, Variant};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use error::ApiError;
+use ring::rand::{SecureRandom, SystemRandom};
+use schema::local_credentials;
+
+/// Length of the random salt of each password hash
+const SALT_LENGTH: usize = 16;
+
+#[derive(Queryable, Clone)]
+/// Describes the password of a local account, as present in the database.
+/// It belongs to the identity of the user on the local `AuthProvider`
+pub struct LocalCredential {
+    /// The unique ID of the credential
+    pub id: Option<i32>,
+    /// The ID of the local `Identity` the password belongs to
+    pub identity_id: i32,
+    /// The argon2 hash of the password, in its encoded form holding the parameters and the salt
+    pub password_hash: String,
+    /// Last time the password has been changed
+    pub updated_at: NaiveDateTime,
+}
+
+impl LocalCredential {
+    /// Finds the password of the given local identity
+    pub fn find_for_identity(identity_id: i32, db: &diesel::SqliteConnection) -> Option<Self> {
+        local_credentials::table
+            .filter(local_credentials::identity_id.eq(identity_id))
+            .first::<Self>(db)
+            .ok()
+    }
+
+    /// Sets the password of a freshly created local identity
+    pub fn create(
+        identity_id: i32,
+        password: &str,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::insert_into(local_credentials::table)
+            .values((
+                local_credentials::identity_id.eq(identity_id),
+                local_credentials::password_hash.eq(hash_password(password)?),
+                local_credentials::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(db)
+            .map(|_| ())
+            .map_err(ApiError::from)
+    }
+
+    /// Checks whether the given password is the right one
+    pub fn verify(&self, password: &str) -> bool {
+        argon2::verify_encoded(&self.password_hash, password.as_bytes()).unwrap_or(false)
+    }
+
+    /// Spends as long as checking a password would, when there is no password to check.
+    /// Always fails
+    pub fn verify_missing(password: &str) -> bool {
+        let _ = hash_password(password);
+        false
+    }
+
+    /// Replaces the password
+    pub fn set_password(
+        &mut self,
+        password: &str,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        let password_hash = hash_password(password)?;
+        let updated_at = Utc::now().naive_utc();
+        diesel::update(local_credentials::table.filter(local_credentials::id.eq(self.id)))
+            .set((
+                local_credentials::password_hash.eq(&password_hash),
+                local_credentials::updated_at.eq(updated_at),
+            ))
+            .execute(db)?;
+
+        self.password_hash = password_hash;
+        self.updated_at = updated_at;
+        Ok(())
+    }
+}
+
+/// Hashes a password with argon2id and a random salt
+fn hash_password(password: &str) -> Result<String, ApiError> {
+    let mut salt = [0; SALT_LENGTH];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| ApiError::Internal(format!("Failed to generate a salt")))?;
+
+    let config = Config {
+        variant: Variant::Argon2id,
+        ..Config::default()
+    };
+    argon2::hash_encoded(password.as_bytes(), &salt, &config)
+        .map_err(|_| ApiError::Internal(format!("Failed to hash the password")))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{hash_password, LocalCredential};
+    use chrono::Utc;
+
+    #[test]
+    pub fn verify_password() {
+        let credential = LocalCredential {
+            id: Some(1),
+            identity_id: 1,
+            password_hash: hash_password("correct horse").expect("Hashed password"),
+            updated_at: Utc::now().naive_utc(),
+        };
+        assert!(credential.verify("correct horse"));
+        assert!(!credential.verify("battery staple"));
+
+        // Each hash has its own salt
+        assert_ne!(
+            hash_password("correct horse"),
+            hash_password("correct horse")
+        );
+    }
+}
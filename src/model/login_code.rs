@@ -0,0 +1,105 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use error::ApiError;
+use model::token::{token_prefix, TokenHasher};
+use schema::login_codes;
+
+/// Number of seconds a login code stays valid
+const CODE_LIFETIME_SECONDS: i64 = 60;
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a code given to the frontend at the end of a login, through the address it is
+/// redirected to. The frontend trades it for the token of a new session, so that the token
+/// itself never goes through the browser's history or its readable cookies
+pub struct LoginCode {
+    /// The unique ID of the code
+    pub id: Option<i32>,
+    /// The ID of the `User` who logged in
+    pub user_id: i32,
+    /// The public part of the code
+    pub code_prefix: String,
+    /// The keyed hash of the code
+    pub code_hash: String,
+    /// The code can't be used after this date
+    pub expires_at: NaiveDateTime,
+}
+
+impl LoginCode {
+    /// Issues a new code for the given user. Returns the code to give to the frontend
+    pub fn create(
+        user_id: i32,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<String, ApiError> {
+        // Codes that have never been traded are cleaned up along the way
+        let now = Utc::now().naive_utc();
+        diesel::delete(login_codes::table.filter(login_codes::expires_at.le(now))).execute(db)?;
+
+        let (code, hashed_code) = hasher.generate();
+        diesel::insert_into(login_codes::table)
+            .values((
+                login_codes::user_id.eq(user_id),
+                login_codes::code_prefix.eq(hashed_code.prefix),
+                login_codes::code_hash.eq(hashed_code.hash),
+                login_codes::expires_at.eq(now + Duration::seconds(CODE_LIFETIME_SECONDS)),
+            ))
+            .execute(db)?;
+
+        Ok(code)
+    }
+
+    /// Uses the given code, as long as it has not expired.
+    /// A code can only be used once. Returns the ID of the user who logged in
+    pub fn consume(
+        code: &str,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<i32, ()> {
+        let prefix = token_prefix(code).ok_or(())?;
+        let login_code = login_codes::table
+            .filter(login_codes::code_prefix.eq(prefix))
+            .filter(login_codes::expires_at.gt(Utc::now().naive_utc()))
+            .first::<Self>(db)
+            .map_err(|_| ())?;
+
+        hasher.consume_once(code, &login_code.code_hash, || {
+            diesel::delete(login_codes::table.filter(login_codes::id.eq(login_code.id))).execute(db)
+        })?;
+
+        Ok(login_code.user_id)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::LoginCode;
+    use chrono::{Duration, Utc};
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use model::token::{token_prefix, TokenHasher};
+    use rocket::Rocket;
+    use schema::login_codes;
+
+    #[test]
+    pub fn consume_login_code_once() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        let code = LoginCode::create(42, &hasher, &db).expect("Valid code");
+        assert_eq!(LoginCode::consume(&code, &hasher, &db), Ok(42));
+
+        // The code can't be traded twice
+        assert_eq!(LoginCode::consume(&code, &hasher, &db), Err(()));
+
+        // Nor once it has expired
+        let code = LoginCode::create(42, &hasher, &db).expect("Valid code");
+        diesel::update(
+            login_codes::table.filter(login_codes::code_prefix.eq(token_prefix(&code).unwrap())),
+        )
+        .set(login_codes::expires_at.eq(Utc::now().naive_utc() - Duration::seconds(1)))
+        .execute(&**db)
+        .expect("Valid update");
+        assert_eq!(LoginCode::consume(&code, &hasher, &db), Err(()));
+    }
+}
@@ -1,2 +1,14 @@
+pub mod access_token;
+pub mod account;
+pub mod audit_log;
 pub mod auth_service;
+pub mod email_login;
+pub mod encrypted_token;
+pub mod identity;
+pub mod local_credential;
+pub mod login_code;
+pub mod role;
+pub mod session;
+pub mod star;
+pub mod token;
 pub mod user;
@@ -0,0 +1,420 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::{guard_failure, ApiError};
+use model::audit_log::{AuditEvent, AuditRecord};
+use model::auth_service::AuthProvider;
+use model::user::{APIUser, User};
+use rocket::request::{FromRequest, Request};
+use rocket::Outcome;
+use rocket_contrib::json::Json;
+use schema::user_roles;
+use state::admin_config::AdminConfig;
+
+/// A role granting privileges to the users holding it
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manages the users, the puzzles and the leaderboards
+    Admin,
+}
+
+impl Role {
+    /// Gets the name of the role, as stored in the database
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Finds a role by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a role granted to a user, as present in the database
+pub struct UserRole {
+    /// The unique ID of the grant
+    pub id: Option<i32>,
+    /// The ID of the `User` holding the role
+    pub user_id: i32,
+    /// The name of the `Role`
+    pub role: String,
+    /// When the role has been granted
+    pub granted_at: NaiveDateTime,
+}
+
+impl UserRole {
+    /// Lists every role granted to any user
+    pub fn list(db: &diesel::SqliteConnection) -> Result<Vec<Self>, ApiError> {
+        user_roles::table.load::<Self>(db).map_err(ApiError::from)
+    }
+
+    /// Lists the roles of the given user. Roles that don't exist anymore are left out
+    pub fn list_for_user(
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<Role>, ApiError> {
+        let roles = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .select(user_roles::role)
+            .load::<String>(db)?;
+        Ok(roles
+            .iter()
+            .filter_map(|role| Role::from_name(role))
+            .collect())
+    }
+
+    /// Checks whether the given user holds the given role
+    pub fn has_role(
+        user_id: i32,
+        role: Role,
+        db: &diesel::SqliteConnection,
+    ) -> Result<bool, ApiError> {
+        let count: i64 = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .filter(user_roles::role.eq(role.name()))
+            .count()
+            .get_result(db)?;
+        Ok(count > 0)
+    }
+
+    /// Grants a role to the given user. Returns whether the user didn't already hold it
+    pub fn grant(
+        user_id: i32,
+        role: Role,
+        db: &diesel::SqliteConnection,
+    ) -> Result<bool, ApiError> {
+        if UserRole::has_role(user_id, role, db)? {
+            return Ok(false);
+        }
+
+        diesel::insert_into(user_roles::table)
+            .values((
+                user_roles::user_id.eq(user_id),
+                user_roles::role.eq(role.name()),
+                user_roles::granted_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(db)?;
+        Ok(true)
+    }
+
+    /// Revokes a role from the given user.
+    /// The last admin can't be revoked, as no one could grant the role anymore.
+    /// Returns whether the user held the role
+    pub fn revoke(
+        user_id: i32,
+        role: Role,
+        db: &diesel::SqliteConnection,
+    ) -> Result<bool, ApiError> {
+        if role == Role::Admin {
+            let admins: i64 = user_roles::table
+                .filter(user_roles::role.eq(role.name()))
+                .filter(user_roles::user_id.ne(user_id))
+                .count()
+                .get_result(db)?;
+            if admins == 0 {
+                return Err(ApiError::Conflict(format!(
+                    "The last admin can't be revoked"
+                )));
+            }
+        }
+
+        diesel::delete(
+            user_roles::table
+                .filter(user_roles::user_id.eq(user_id))
+                .filter(user_roles::role.eq(role.name())),
+        )
+        .execute(db)
+        .map(|count| count > 0)
+        .map_err(ApiError::from)
+    }
+
+    /// Makes the given user the first admin if it logged in with the configured identity, as long
+    /// as there is no admin yet. The identity is only trusted by its immutable ID, since
+    /// usernames can be taken over. Returns whether the user has been made admin
+    pub fn bootstrap_admin(
+        config: &AdminConfig,
+        auth_provider: i32,
+        ext_id: &str,
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<bool, ApiError> {
+        if config.get_ext_id() != ext_id
+            || AuthProvider::find_id(config.get_provider(), db) != Some(auth_provider)
+        {
+            return Ok(false);
+        }
+
+        let admins: i64 = user_roles::table
+            .filter(user_roles::role.eq(Role::Admin.name()))
+            .count()
+            .get_result(db)?;
+        if admins > 0 {
+            return Ok(false);
+        }
+
+        UserRole::grant(user_id, Role::Admin, db)
+    }
+}
+
+/// A logged in user holding the admin role
+pub struct AdminUser(pub APIUser);
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminUser {
+    type Error = ApiError;
+
+    fn from_request(request: &'a Request<'r>) -> rocket::request::Outcome<Self, Self::Error> {
+        // Failures to authenticate the user have already been described by `APIUser`
+        let api_user = match request.guard::<APIUser>() {
+            Outcome::Success(api_user) => api_user,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(()) => return Outcome::Forward(()),
+        };
+
+        let db = match request.guard::<DatabaseConn>() {
+            Outcome::Success(db) => db,
+            _ => {
+                return guard_failure(
+                    request,
+                    ApiError::Database(format!("Failed to connect to database")),
+                )
+            }
+        };
+
+        // Administration is only done from the browser, never with personal access tokens
+        if let Err(e) = api_user.require_session() {
+            return guard_failure(request, e);
+        }
+
+        match UserRole::has_role(api_user.id, Role::Admin, &db) {
+            Ok(true) => Outcome::Success(AdminUser(api_user)),
+            Ok(false) => guard_failure(
+                request,
+                ApiError::Forbidden("Only admins can do this".into()),
+            ),
+            Err(e) => guard_failure(request, e),
+        }
+    }
+}
+
+/// Describes a user as seen by the admins
+#[derive(Serialize, Debug)]
+pub struct ManagedUser {
+    pub id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub roles: Vec<Role>,
+    /// Whether the user is suspended right now
+    pub suspended: bool,
+    pub suspension_reason: Option<String>,
+    pub suspended_until: Option<NaiveDateTime>,
+    /// When the user deleted its account, if it did
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// Lists every user along with their roles
+#[get("/admin/users")]
+pub fn list_users(_admin: AdminUser, db: DatabaseConn) -> Result<Json<Vec<ManagedUser>>, ApiError> {
+    let roles = UserRole::list(&db)?;
+    let users = User::list(&db)?
+        .into_iter()
+        .map(|user| {
+            let id = user.id.unwrap();
+            ManagedUser {
+                id,
+                suspended: user.is_suspended(),
+                username: user.username,
+                display_name: user.display_name,
+                created_at: user.created_at,
+                suspension_reason: user.suspension_reason,
+                suspended_until: user.suspended_until,
+                deleted_at: user.deleted_at,
+                roles: roles
+                    .iter()
+                    .filter(|role| role.user_id == id)
+                    .filter_map(|role| Role::from_name(&role.role))
+                    .collect(),
+            }
+        })
+        .collect();
+    Ok(Json(users))
+}
+
+/// Grants a role to a user
+#[put("/admin/users/<id>/roles/<role>")]
+pub fn grant_role(
+    id: i32,
+    role: String,
+    admin: AdminUser,
+    db: DatabaseConn,
+) -> Result<(), ApiError> {
+    let role = parse_role(&role)?;
+    User::find_by_id(id, &db).map_err(|_| ApiError::NotFound)?;
+    if UserRole::grant(id, role, &db)? {
+        AuditRecord::new(AuditEvent::RoleGranted)
+            .with_user(id)
+            .with_actor((admin.0).id)
+            .with_details(role.name().into())
+            .record(&db);
+    }
+    Ok(())
+}
+
+/// Revokes a role from a user
+#[delete("/admin/users/<id>/roles/<role>")]
+pub fn revoke_role(
+    id: i32,
+    role: String,
+    admin: AdminUser,
+    db: DatabaseConn,
+) -> Result<(), ApiError> {
+    let role = parse_role(&role)?;
+    match UserRole::revoke(id, role, &db)? {
+        true => {
+            AuditRecord::new(AuditEvent::RoleRevoked)
+                .with_user(id)
+                .with_actor((admin.0).id)
+                .with_details(role.name().into())
+                .record(&db);
+            Ok(())
+        }
+        false => Err(ApiError::NotFound),
+    }
+}
+
+/// Why and how long a user is suspended
+#[derive(Deserialize)]
+pub struct Suspension {
+    reason: String,
+    /// The suspension lasts until it is lifted when there is no end date
+    until: Option<NaiveDateTime>,
+}
+
+/// Suspends a user, who can't log in nor use its tokens anymore. Its data is kept
+#[put("/admin/users/<id>/suspension", format = "json", data = "<suspension>")]
+pub fn suspend_user(
+    id: i32,
+    suspension: Json<Suspension>,
+    admin: AdminUser,
+    db: DatabaseConn,
+) -> Result<(), ApiError> {
+    if id == (admin.0).id {
+        return Err(ApiError::Conflict("Admins can't suspend themselves".into()));
+    }
+    let suspension = suspension.into_inner();
+    if suspension.reason.trim().is_empty() {
+        return Err(ApiError::Validation("A suspension needs a reason".into()));
+    }
+
+    let mut user = User::find_by_id(id, &db).map_err(|_| ApiError::NotFound)?;
+    let reason = suspension.reason.trim();
+    user.suspend(reason.into(), suspension.until, &db)?;
+    AuditRecord::new(AuditEvent::UserSuspended)
+        .with_user(id)
+        .with_actor((admin.0).id)
+        .with_details(match suspension.until {
+            Some(until) => format!("{} (until {})", reason, until),
+            None => reason.into(),
+        })
+        .record(&db);
+    Ok(())
+}
+
+/// Lifts the suspension of a user
+#[delete("/admin/users/<id>/suspension")]
+pub fn unsuspend_user(id: i32, admin: AdminUser, db: DatabaseConn) -> Result<(), ApiError> {
+    let mut user = User::find_by_id(id, &db).map_err(|_| ApiError::NotFound)?;
+    if user.suspended_at.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    user.unsuspend(&db)?;
+    AuditRecord::new(AuditEvent::UserUnsuspended)
+        .with_user(id)
+        .with_actor((admin.0).id)
+        .record(&db);
+    Ok(())
+}
+
+/// Finds the role with the given name, given by the admin
+fn parse_role(name: &str) -> Result<Role, ApiError> {
+    Role::from_name(name).ok_or(ApiError::Validation(format!("Unknown role {}", name)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Role, UserRole};
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use error::ApiError;
+    use model::auth_service::AuthProvider;
+    use model::user::User;
+    use rocket::Rocket;
+    use schema::user_roles;
+    use state::admin_config::AdminConfig;
+
+    #[test]
+    pub fn grant_and_revoke_roles() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+
+        // Everything is rolled back, so that the admins of previous runs don't get in the way
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(user_roles::table).execute(&**db)?;
+            let first = User::create("first_admin".into(), &db)?.id.unwrap();
+            let second = User::create("second_admin".into(), &db)?.id.unwrap();
+
+            assert_eq!(UserRole::grant(first, Role::Admin, &db), Ok(true));
+            assert_eq!(UserRole::grant(first, Role::Admin, &db), Ok(false));
+            assert_eq!(UserRole::has_role(first, Role::Admin, &db), Ok(true));
+
+            // There has to be an admin left
+            assert!(UserRole::revoke(first, Role::Admin, &db).is_err());
+            assert_eq!(UserRole::grant(second, Role::Admin, &db), Ok(true));
+            assert_eq!(UserRole::revoke(first, Role::Admin, &db), Ok(true));
+            assert_eq!(UserRole::has_role(first, Role::Admin, &db), Ok(false));
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn bootstrap_first_admin() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let config: AdminConfig =
+            toml::from_str("provider = \"github\"\next_id = \"1234\"").expect("Valid config");
+
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(user_roles::table).execute(&**db)?;
+            let github = AuthProvider::find_or_create("github", &db)?;
+            let gitlab = AuthProvider::find_or_create("gitlab", &db)?;
+            let user = User::create("santa".into(), &db)?.id.unwrap();
+
+            // Only the configured identity is trusted, and only while there is no admin
+            assert_eq!(
+                UserRole::bootstrap_admin(&config, github, "4321", user, &db),
+                Ok(false)
+            );
+            assert_eq!(
+                UserRole::bootstrap_admin(&config, gitlab, "1234", user, &db),
+                Ok(false)
+            );
+            assert_eq!(
+                UserRole::bootstrap_admin(&config, github, "1234", user, &db),
+                Ok(true)
+            );
+            let other = User::create("grinch".into(), &db)?.id.unwrap();
+            assert_eq!(
+                UserRole::bootstrap_admin(&config, github, "1234", other, &db),
+                Ok(false)
+            );
+            Ok(())
+        });
+    }
+}
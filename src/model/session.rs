@@ -0,0 +1,344 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use model::token::{token_prefix, HashedToken, TokenHasher};
+use model::user::APIUser;
+use rocket::http::{Cookie, Cookies};
+use rocket::request::{FromRequest, Request};
+use rocket::Outcome;
+use rocket_contrib::json::Json;
+use schema::{legacy_tokens, sessions};
+
+/// Number of days a session stays valid after the login
+const SESSION_LIFETIME_DAYS: i64 = 30;
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a session of a user, as present in the database.
+/// A session is created on each login and holds the internal token of our platform
+pub struct Session {
+    /// The unique ID of the session
+    pub id: Option<i32>,
+    /// The ID of the `User` owning the session
+    pub user_id: i32,
+    /// The public part of the internal token of the session
+    pub token_prefix: String,
+    /// The keyed hash of the internal token. The token itself is only known by the client
+    pub token_hash: String,
+    /// When the user logged in
+    pub created_at: NaiveDateTime,
+    /// Last time the session has been used to access the API
+    pub last_seen: NaiveDateTime,
+    /// The session can't be used after this date
+    pub expires_at: NaiveDateTime,
+    /// The user agent of the client that logged in, if any
+    pub user_agent: Option<String>,
+}
+
+impl Session {
+    /// Opens a new session for the given user.
+    /// Returns the session along with its token, which has to be given to the client
+    pub fn create(
+        user_id: i32,
+        user_agent: Option<String>,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(Self, String), ApiError> {
+        let (token, hashed_token) = hasher.generate();
+        let new_session = InsertSession::new(user_id, hashed_token, user_agent);
+        diesel::insert_into(sessions::table)
+            .values(&new_session)
+            .execute(db)?;
+
+        Session::find_valid(&token, hasher, db)
+            .map(|session| (session, token))
+            .map_err(|_| ApiError::Internal(format!("Failed to create the session")))
+    }
+
+    /// Finds the session matching the given token, as long as it has not expired
+    pub fn find_valid(
+        token: &str,
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Self, ()> {
+        let prefix = token_prefix(token).ok_or(())?;
+        let session = sessions::table
+            .filter(sessions::token_prefix.eq(prefix))
+            .filter(sessions::expires_at.gt(now()))
+            .first::<Self>(db)
+            .map_err(|_| ())?;
+
+        if hasher.verify(token, &session.token_hash) {
+            Ok(session)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Lists the sessions of the given user that have not expired yet
+    pub fn list_for_user(
+        user_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<Vec<Self>, ApiError> {
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::expires_at.gt(now()))
+            .order(sessions::last_seen.desc())
+            .load::<Self>(db)
+            .map_err(ApiError::from)
+    }
+
+    /// Revokes a session of the given user.
+    /// Returns whether a session has been revoked
+    pub fn revoke(id: i32, user_id: i32, db: &diesel::SqliteConnection) -> Result<bool, ApiError> {
+        diesel::delete(
+            sessions::table
+                .filter(sessions::id.eq(id))
+                .filter(sessions::user_id.eq(user_id)),
+        )
+        .execute(db)
+        .map(|count| count > 0)
+        .map_err(ApiError::from)
+    }
+
+    /// Revokes every session of the given user, except the given one.
+    /// Returns the number of revoked sessions
+    pub fn revoke_others(
+        user_id: i32,
+        kept_id: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<usize, ApiError> {
+        diesel::delete(
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::id.ne(kept_id)),
+        )
+        .execute(db)
+        .map_err(ApiError::from)
+    }
+
+    /// Hashes the tokens of the sessions opened before tokens were stored hashed. The migration
+    /// could only keep them aside, as the key of the hash lives in the configuration.
+    ///
+    /// The tokens are hashed in place rather than re-issued, since the clients holding them
+    /// couldn't receive new ones: re-issuing would log everyone out. Hashing protects tokens from
+    /// a leak of the database, which re-issuing wouldn't undo for tokens that already lay there
+    /// in plain text. Users can still revoke those sessions, and the plain tokens are gone once
+    /// the server has started.
+    /// Returns the number of hashed tokens
+    pub fn hash_legacy_tokens(
+        hasher: &TokenHasher,
+        db: &diesel::SqliteConnection,
+    ) -> Result<usize, ApiError> {
+        let legacy = legacy_tokens::table.load::<(i32, String)>(db)?;
+
+        db.transaction::<_, diesel::result::Error, _>(|| {
+            for &(session_id, ref token) in &legacy {
+                diesel::update(sessions::table.filter(sessions::id.eq(session_id)))
+                    .set(sessions::token_hash.eq(hasher.hash(token)))
+                    .execute(db)?;
+            }
+            diesel::delete(legacy_tokens::table).execute(db)?;
+            Ok(legacy.len())
+        })
+        .map_err(ApiError::from)
+    }
+
+    /// Marks the session as being used right now
+    pub fn touch(&self, db: &diesel::SqliteConnection) -> Result<(), ApiError> {
+        diesel::update(sessions::table.filter(sessions::id.eq(self.id)))
+            .set(sessions::last_seen.eq(now()))
+            .execute(db)
+            .map(|_| ())
+            .map_err(ApiError::from)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct InsertSession {
+    /// The ID of the `User` owning the session
+    pub user_id: i32,
+    /// The public part of the *internal* token of the session
+    pub token_prefix: String,
+    /// The keyed hash of the *internal* token of the session
+    pub token_hash: String,
+    /// When the session has been created
+    pub created_at: NaiveDateTime,
+    /// Last time the session has been used, which is its creation at first
+    pub last_seen: NaiveDateTime,
+    /// When the session expires
+    pub expires_at: NaiveDateTime,
+    /// The user agent of the client that logged in
+    pub user_agent: Option<String>,
+}
+
+impl InsertSession {
+    /// Creates a new instance of `InsertSession`, that Diesel will use to create a session
+    pub fn new(user_id: i32, token: HashedToken, user_agent: Option<String>) -> Self {
+        let created_at = now();
+
+        InsertSession {
+            user_id,
+            token_prefix: token.prefix,
+            token_hash: token.hash,
+            created_at,
+            last_seen: created_at,
+            expires_at: created_at + Duration::days(SESSION_LIFETIME_DAYS),
+            user_agent,
+        }
+    }
+}
+
+/// Describes a session as exposed by the API. The token itself is never sent back
+#[derive(Serialize, Debug)]
+pub struct APISession {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    /// Whether this is the session used to make the request
+    pub current: bool,
+}
+
+impl APISession {
+    pub fn new_from_session(session: Session, current_session_id: i32) -> Self {
+        let id = session.id.unwrap();
+        APISession {
+            id,
+            created_at: session.created_at,
+            last_seen: session.last_seen,
+            expires_at: session.expires_at,
+            user_agent: session.user_agent,
+            current: id == current_session_id,
+        }
+    }
+}
+
+/// The `User-Agent` header of a request, if any
+pub struct UserAgent(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> rocket::request::Outcome<Self, Self::Error> {
+        let user_agent = request.headers().get_one("User-Agent").map(Into::into);
+        Outcome::Success(UserAgent(user_agent))
+    }
+}
+
+/// Gets the current date, as stored in the database
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Closes the session used to make the request
+#[post("/logout")]
+pub fn logout(api_user: APIUser, db: DatabaseConn, mut cookies: Cookies) -> Result<(), ApiError> {
+    let session_id = api_user.require_session()?;
+    cookies.remove(Cookie::named("api_token"));
+    Session::revoke(session_id, api_user.id, &db).map(|_| ())
+}
+
+/// Lists the active sessions of the user
+#[get("/sessions")]
+pub fn get_sessions(
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<Vec<APISession>>, ApiError> {
+    let session_id = api_user.require_session()?;
+    let sessions = Session::list_for_user(api_user.id, &db)?
+        .into_iter()
+        .map(|session| APISession::new_from_session(session, session_id))
+        .collect();
+    Ok(Json(sessions))
+}
+
+/// Revokes one of the sessions of the user, logging out the matching device
+#[delete("/sessions/<id>")]
+pub fn delete_session(id: i32, api_user: APIUser, db: DatabaseConn) -> Result<(), ApiError> {
+    api_user.require_session()?;
+    match Session::revoke(id, api_user.id, &db)? {
+        true => Ok(()),
+        false => Err(ApiError::NotFound),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{InsertSession, Session};
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use error::ApiError;
+    use model::token::{HashedToken, TokenHasher};
+    use model::user::User;
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+    use rocket::Rocket;
+    use schema::{legacy_tokens, sessions};
+
+    #[test]
+    pub fn create_and_revoke_session() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        // A freshly created session should be usable
+        let (session, token) =
+            Session::create(1, Some("test_agent".into()), &hasher, &db).expect("Valid session");
+        assert!(Session::find_valid(&token, &hasher, &db).is_ok());
+
+        // Only the owner of the session can revoke it
+        assert_eq!(Session::revoke(session.id.unwrap(), 2, &db), Ok(false));
+        assert_eq!(Session::revoke(session.id.unwrap(), 1, &db), Ok(true));
+
+        // The token shouldn't be accepted anymore
+        assert!(Session::find_valid(&token, &hasher, &db).is_err());
+    }
+
+    #[test]
+    pub fn hash_legacy_tokens() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+        let hasher = TokenHasher::new("test_key");
+
+        // Everything is rolled back, so that the sessions of previous runs don't get in the way
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(legacy_tokens::table).execute(&**db)?;
+            let user_id = User::create("legacy_user".into(), &db)?.id.unwrap();
+
+            // Sessions are migrated with their plain token kept aside
+            let legacy: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(30)
+                .collect();
+            let hashed = HashedToken {
+                prefix: legacy[..8].into(),
+                hash: String::new(),
+            };
+            diesel::insert_into(sessions::table)
+                .values(&InsertSession::new(user_id, hashed, None))
+                .execute(&**db)?;
+            let session_id = sessions::table
+                .select(sessions::id)
+                .order(sessions::id.desc())
+                .first::<Option<i32>>(&**db)?
+                .unwrap();
+            diesel::insert_into(legacy_tokens::table)
+                .values((
+                    legacy_tokens::session_id.eq(session_id),
+                    legacy_tokens::token.eq(&legacy),
+                ))
+                .execute(&**db)?;
+            assert!(Session::find_valid(&legacy, &hasher, &db).is_err());
+
+            // Once hashed, the same token still opens the session
+            assert_eq!(Session::hash_legacy_tokens(&hasher, &db), Ok(1));
+            let session = Session::find_valid(&legacy, &hasher, &db).expect("Valid legacy session");
+            assert_eq!(session.id, Some(session_id));
+            assert_eq!(Session::hash_legacy_tokens(&hasher, &db), Ok(0));
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::{NaiveDateTime, Utc};
+use db::DatabaseConn;
+use diesel::prelude::*;
+use error::ApiError;
+use model::access_token::Scope;
+use model::user::{APIUser, User};
+use rocket_contrib::json::Json;
+use schema::{stars, users};
+
+/// Number of days with a puzzle
+const DAYS: i32 = 25;
+
+/// Number of parts of each puzzle, each one earning a star
+const PARTS: i32 = 2;
+
+#[derive(Queryable, Clone, Debug)]
+/// Describes a star earned by a user, as present in the database
+pub struct Star {
+    /// The unique ID of the star
+    pub id: Option<i32>,
+    /// The ID of the `User` who earned the star
+    pub user_id: i32,
+    /// The day of the puzzle, from 1 to 25
+    pub day: i32,
+    /// The part of the puzzle, 1 or 2
+    pub part: i32,
+    /// When the star has been earned
+    pub earned_at: NaiveDateTime,
+}
+
+impl Star {
+    /// Records that the given user solved the given part of a day's puzzle.
+    /// Returns whether the star hadn't already been earned
+    pub fn earn(
+        user_id: i32,
+        day: i32,
+        part: i32,
+        db: &diesel::SqliteConnection,
+    ) -> Result<bool, ApiError> {
+        if day < 1 || day > DAYS || part < 1 || part > PARTS {
+            return Err(ApiError::Validation(format!(
+                "There is no part {} of day {}",
+                part, day
+            )));
+        }
+
+        let earned: i64 = stars::table
+            .filter(stars::user_id.eq(user_id))
+            .filter(stars::day.eq(day))
+            .filter(stars::part.eq(part))
+            .count()
+            .get_result(db)?;
+        if earned > 0 {
+            return Ok(false);
+        }
+
+        diesel::insert_into(stars::table)
+            .values((
+                stars::user_id.eq(user_id),
+                stars::day.eq(day),
+                stars::part.eq(part),
+                stars::earned_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(db)?;
+        Ok(true)
+    }
+
+    /// Counts the stars earned by the given user
+    pub fn count_for_user(user_id: i32, db: &diesel::SqliteConnection) -> Result<i64, ApiError> {
+        stars::table
+            .filter(stars::user_id.eq(user_id))
+            .count()
+            .get_result(db)
+            .map_err(ApiError::from)
+    }
+}
+
+/// Describes a user on the leaderboard, as exposed by the API
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    pub id: i32,
+    /// The name to show, unless the user wants to appear anonymously
+    pub name: String,
+    pub public_link: Option<String>,
+    pub stars: i64,
+}
+
+impl LeaderboardEntry {
+    /// Builds the entry of the given user
+    fn new(user: User, stars: i64) -> Self {
+        let id = user.id.unwrap();
+        if user.anonymous {
+            return LeaderboardEntry {
+                id,
+                name: format!("Anonymous user #{}", id),
+                public_link: None,
+                stars,
+            };
+        }
+
+        LeaderboardEntry {
+            id,
+            name: user.display_name.unwrap_or(user.username),
+            public_link: user.public_link,
+            stars,
+        }
+    }
+
+    /// Ranks the users who earned stars, from the most stars to the fewest, the oldest users
+    /// first in case of a tie. Suspended users are left out
+    pub fn list(db: &diesel::SqliteConnection) -> Result<Vec<Self>, ApiError> {
+        // One row per star, from the oldest user to the newest
+        let earners = stars::table
+            .inner_join(users::table)
+            .filter(User::visible())
+            .select(users::all_columns)
+            .order(users::id.asc())
+            .load::<User>(db)?;
+
+        let mut ranking: Vec<(User, i64)> = Vec::new();
+        for user in earners {
+            if let Some(&mut (ref last, ref mut stars)) = ranking.last_mut() {
+                if last.id == user.id {
+                    *stars += 1;
+                    continue;
+                }
+            }
+            ranking.push((user, 1));
+        }
+        // The sort is stable, so that ties stay ordered by ID
+        ranking.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Ok(ranking
+            .into_iter()
+            .map(|(user, stars)| LeaderboardEntry::new(user, stars))
+            .collect())
+    }
+}
+
+/// Gets the leaderboard
+#[get("/leaderboard")]
+pub fn get_leaderboard(
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<Vec<LeaderboardEntry>>, ApiError> {
+    api_user.require_scope(Scope::ReadLeaderboards)?;
+    Ok(Json(LeaderboardEntry::list(&db)?))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{LeaderboardEntry, Star};
+    use chrono::{Duration, Utc};
+    use db::TestDatabase;
+    use diesel::prelude::*;
+    use error::ApiError;
+    use model::user::{Profile, User};
+    use rocket::Rocket;
+    use schema::stars;
+
+    #[test]
+    pub fn count_earned_stars() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+
+        db.test_transaction::<_, ApiError, _>(|| {
+            let user = User::create("star_collector".into(), &db)?;
+            let user_id = user.id.unwrap();
+            assert_eq!(Star::count_for_user(user_id, &db), Ok(0));
+
+            assert_eq!(Star::earn(user_id, 1, 1, &db), Ok(true));
+            assert_eq!(Star::earn(user_id, 1, 2, &db), Ok(true));
+            assert_eq!(Star::earn(user_id, 2, 1, &db), Ok(true));
+            // Solving a part again doesn't earn another star
+            assert_eq!(Star::earn(user_id, 1, 1, &db), Ok(false));
+            assert!(Star::earn(user_id, 26, 1, &db).is_err());
+            assert!(Star::earn(user_id, 1, 3, &db).is_err());
+
+            assert_eq!(Star::count_for_user(user_id, &db), Ok(3));
+            assert_eq!(Profile::for_user(user, &db)?.stars, 3);
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn hide_suspended_users_from_leaderboard() {
+        let rocket: Rocket = rocket::ignite().attach(TestDatabase::fairing());
+        let db = TestDatabase::get_one(&rocket).expect("Valid database connection");
+
+        // Everything is rolled back, so that the stars of previous runs don't get in the way
+        db.test_transaction::<_, ApiError, _>(|| {
+            diesel::delete(stars::table).execute(&**db)?;
+            let mut first = User::create("first_solver".into(), &db)?;
+            let mut second = User::create("second_solver".into(), &db)?;
+            let first_id = first.id.unwrap();
+            let second_id = second.id.unwrap();
+            Star::earn(first_id, 1, 1, &db)?;
+            Star::earn(second_id, 1, 1, &db)?;
+            Star::earn(second_id, 1, 2, &db)?;
+            second.set_profile(None, true, None, &db)?;
+
+            let entry = |id: i32, name: String, stars: i64| LeaderboardEntry {
+                id,
+                name,
+                public_link: None,
+                stars,
+            };
+            assert_eq!(
+                LeaderboardEntry::list(&db),
+                Ok(vec![
+                    entry(second_id, format!("Anonymous user #{}", second_id), 2),
+                    entry(first_id, "first_solver".into(), 1),
+                ])
+            );
+
+            second.suspend("Leaderboard cheating".into(), None, &db)?;
+            assert_eq!(
+                LeaderboardEntry::list(&db),
+                Ok(vec![entry(first_id, "first_solver".into(), 1)])
+            );
+
+            // Ended suspensions don't hide users anymore
+            let yesterday = Utc::now().naive_utc() - Duration::days(1);
+            first.suspend("Leaderboard cheating".into(), Some(yesterday), &db)?;
+            assert_eq!(
+                LeaderboardEntry::list(&db),
+                Ok(vec![entry(first_id, "first_solver".into(), 1)])
+            );
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use ring::constant_time::verify_slices_are_equal;
+use ring::{digest, hmac};
+
+/// Length of the public part of a token, used to find it in the database
+const PREFIX_LENGTH: usize = 8;
+
+/// Length of the secret part of a token
+const SECRET_LENGTH: usize = 32;
+
+/// Length of the tokens issued before they were hashed, which are found by their first characters
+const LEGACY_LENGTH: usize = 30;
+
+/// Keyed hasher for the internal tokens of our platform.
+///
+/// Tokens look like `<prefix>.<secret>`. Only the prefix and the keyed hash of the whole token
+/// are stored, so that a leaked database doesn't give access to any account.
+pub struct TokenHasher {
+    key: hmac::SigningKey,
+}
+
+/// The stored form of a token
+pub struct HashedToken {
+    /// Public part of the token, used to find it in the database
+    pub prefix: String,
+    /// Keyed hash of the whole token
+    pub hash: String,
+}
+
+impl TokenHasher {
+    /// Creates a new hasher from the secret key of the platform
+    pub fn new(secret: &str) -> Self {
+        TokenHasher {
+            key: hmac::SigningKey::new(&digest::SHA256, secret.as_bytes()),
+        }
+    }
+
+    /// Generates a new random token.
+    /// Returns the token to give to the client, and the form to store in the database
+    pub fn generate(&self) -> (String, HashedToken) {
+        let prefix = random_string(PREFIX_LENGTH);
+        let token = format!("{}.{}", prefix, random_string(SECRET_LENGTH));
+        let hash = self.hash(&token);
+
+        (token, HashedToken { prefix, hash })
+    }
+
+    /// Computes the keyed hash of a token
+    pub fn hash(&self, token: &str) -> String {
+        hmac::sign(&self.key, token.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Checks, in constant time, that the given token matches the stored hash
+    pub fn verify(&self, token: &str, hash: &str) -> bool {
+        verify_slices_are_equal(self.hash(token).as_bytes(), hash.as_bytes()).is_ok()
+    }
+
+    /// Uses a single-use token matching the given stored hash, by deleting what it is stored in
+    /// with `delete`, which gives the number of deleted rows.
+    /// Only the request that actually deletes it gets to use it
+    pub fn consume_once<F, E>(&self, token: &str, hash: &str, delete: F) -> Result<(), ()>
+    where
+        F: FnOnce() -> Result<usize, E>,
+    {
+        if !self.verify(token, hash) {
+            return Err(());
+        }
+
+        match delete() {
+            Ok(1) => Ok(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Gets the public part of a token given by a client, if it is well formed
+pub fn token_prefix(token: &str) -> Option<&str> {
+    let mut parts = token.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(prefix), Some(_)) if prefix.len() == PREFIX_LENGTH => Some(prefix),
+        (Some(legacy), None) if legacy.len() == LEGACY_LENGTH && legacy.is_ascii() => {
+            Some(&legacy[..PREFIX_LENGTH])
+        }
+        _ => None,
+    }
+}
+
+/// Generates a random alphanumeric string of the given length
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .collect::<String>()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{token_prefix, TokenHasher};
+
+    #[test]
+    pub fn verify_generated_token() {
+        let hasher = TokenHasher::new("test_key");
+        let (token, hashed) = hasher.generate();
+
+        assert_eq!(token_prefix(&token), Some(hashed.prefix.as_str()));
+        assert!(hasher.verify(&token, &hashed.hash));
+
+        // Neither another token nor another key should match
+        assert!(!hasher.verify(&format!("{}x", token), &hashed.hash));
+        assert!(!TokenHasher::new("other_key").verify(&token, &hashed.hash));
+    }
+}
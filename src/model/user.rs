@@ -1,57 +1,217 @@
+use chrono::{NaiveDateTime, Utc};
 use db::DatabaseConn;
+use diesel::dsl;
 use diesel::prelude::*;
-use rand::distributions::Alphanumeric;
-use rand::Rng;
-use rocket::http::Cookie;
-use rocket::http::Status;
+use error::{guard_failure, ApiError};
+use model::access_token::{AccessToken, Scope};
+use model::identity::Identity;
+use model::role::{Role, UserRole};
+use model::session::Session;
+use model::star::Star;
+use reqwest::Url;
 use rocket::request::{FromRequest, Request};
 use rocket::Outcome;
+use rocket::State;
+use rocket_contrib::json::Json;
 use schema::users;
+use state::global_config::GlobalConfig;
 
-#[derive(Queryable, Clone, Serialize, Deserialize, Debug)]
+/// Maximal length of a display name, as stored in the database
+const MAX_DISPLAY_NAME_LENGTH: usize = 30;
+
+/// Maximal length of the public link of a user
+const MAX_PUBLIC_LINK_LENGTH: usize = 255;
+
+/// Filter keeping the users who can appear publicly, as returned by `User::visible`
+pub type Visible =
+    dsl::Or<dsl::IsNull<users::suspended_at>, dsl::LtEq<users::suspended_until, NaiveDateTime>>;
+
+#[derive(Queryable, Clone, Debug)]
 /// Describes a user as present in the database
 pub struct User {
     /// The unique ID of the user
     pub id: Option<i32>,
     /// The username of the registered user
     pub username: String,
-    /// The internal token of our platform. Will be stored both
-    /// in the back-end and the front-end
-    pub token: String,
-    /// The ID of the external authentication provider (as referenced)
-    /// in the `AuthProvider` struct
-    pub auth_provider: i32,
-    /// The *external* token, that allows to communicate with the
-    /// public API of an `AuthProvider`
-    pub ext_token: String,
+    /// The name shown instead of the username, if the user chose one
+    pub display_name: Option<String>,
+    /// Whether the user wants to appear anonymously to the other users
+    pub anonymous: bool,
+    /// A link of the user's choice, shown along with its profile
+    pub public_link: Option<String>,
+    /// When the user registered
+    pub created_at: NaiveDateTime,
+    /// When the user has been suspended, if it is
+    pub suspended_at: Option<NaiveDateTime>,
+    /// Why the user has been suspended
+    pub suspension_reason: Option<String>,
+    /// When the suspension ends, if it does
+    pub suspended_until: Option<NaiveDateTime>,
+    /// When the user deleted its account, which has been anonymized since
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
 impl User {
-    /// Creates the representation of a user from the InsertedUser
-    pub fn new_from_inserted(insert_user: InsertUser) -> Self {
-        User {
-            id: None,
-            username: insert_user.username,
-            token: insert_user.token,
-            auth_provider: insert_user.auth_provider,
-            ext_token: insert_user.ext_token,
-        }
+    /// Finds the user with the given ID
+    pub fn find_by_id(id: i32, db: &diesel::SqliteConnection) -> Result<Self, ()> {
+        users::table
+            .filter(users::id.eq(id))
+            .first::<Self>(db)
+            .map_err(|_| ())
     }
 
-    pub fn find_by_token(token: String, db: &diesel::SqliteConnection) -> Result<Self, ()> {
-        let queried = users::table
-            .filter(users::token.eq(token))
+    /// Lists every user, from the oldest to the newest
+    pub fn list(db: &diesel::SqliteConnection) -> Result<Vec<Self>, ApiError> {
+        users::table
+            .order(users::id.asc())
             .load::<Self>(db)
-            .ok();
+            .map_err(ApiError::from)
+    }
 
-        match queried {
-            Some(ref list) if list.len() >= 1 => {
-                let user: &User = list.first().unwrap();
-                Ok((*user).clone())
-            }
-            _ => Err(()),
+    /// Filters the users who can appear publicly, such as on the leaderboards.
+    /// Suspended users are left out, until their suspension ends
+    pub fn visible() -> Visible {
+        users::suspended_at
+            .is_null()
+            .or(users::suspended_until.le(Utc::now().naive_utc()))
+    }
+
+    /// Creates a new user, without any identity yet
+    pub fn create(username: String, db: &diesel::SqliteConnection) -> Result<Self, ApiError> {
+        db.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::insert_into(users::table)
+                .values(&InsertUser::new(username))
+                .execute(db)?;
+            users::table.order(users::id.desc()).first::<Self>(db)
+        })
+        .map_err(ApiError::from)
+    }
+
+    /// Changes the username of the user
+    pub fn set_username(
+        &mut self,
+        username: String,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::update(users::table.filter(users::id.eq(self.id)))
+            .set(users::username.eq(&username))
+            .execute(db)?;
+
+        self.username = username;
+        Ok(())
+    }
+
+    /// Changes the profile of the user, as edited by the user itself
+    pub fn set_profile(
+        &mut self,
+        display_name: Option<String>,
+        anonymous: bool,
+        public_link: Option<String>,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        diesel::update(users::table.filter(users::id.eq(self.id)))
+            .set((
+                users::display_name.eq(&display_name),
+                users::anonymous.eq(anonymous),
+                users::public_link.eq(&public_link),
+            ))
+            .execute(db)?;
+
+        self.display_name = display_name;
+        self.anonymous = anonymous;
+        self.public_link = public_link;
+        Ok(())
+    }
+
+    /// Anonymizes the user once it deleted its account. The user is kept, under a placeholder
+    /// name, so that the leaderboards it appeared on stay consistent
+    pub fn anonymize(&mut self, db: &diesel::SqliteConnection) -> Result<(), ApiError> {
+        let username = format!("Anonymous user #{}", self.id.unwrap());
+        let deleted_at = Utc::now().naive_utc();
+        diesel::update(users::table.filter(users::id.eq(self.id)))
+            .set((
+                users::username.eq(&username),
+                users::display_name.eq(None::<String>),
+                users::anonymous.eq(true),
+                users::public_link.eq(None::<String>),
+                users::deleted_at.eq(deleted_at),
+            ))
+            .execute(db)?;
+
+        self.username = username;
+        self.display_name = None;
+        self.anonymous = true;
+        self.public_link = None;
+        self.deleted_at = Some(deleted_at);
+        Ok(())
+    }
+
+    /// Checks whether the user is suspended right now
+    pub fn is_suspended(&self) -> bool {
+        match (self.suspended_at, self.suspended_until) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(_), Some(until)) => until > Utc::now().naive_utc(),
         }
     }
+
+    /// Checks that the user isn't suspended, as suspended users can neither log in nor use
+    /// their tokens
+    pub fn check_not_suspended(&self) -> Result<(), ApiError> {
+        if !self.is_suspended() {
+            return Ok(());
+        }
+
+        let message = match self.suspended_until {
+            Some(until) => format!(
+                "This account is suspended until {}",
+                until.format("%Y-%m-%d %H:%M UTC")
+            ),
+            None => "This account is suspended".into(),
+        };
+        Err(ApiError::Suspended(match self.suspension_reason {
+            Some(ref reason) => format!("{}: {}", message, reason),
+            None => message,
+        }))
+    }
+
+    /// Suspends the user for the given reason, until the given date if any
+    pub fn suspend(
+        &mut self,
+        reason: String,
+        until: Option<NaiveDateTime>,
+        db: &diesel::SqliteConnection,
+    ) -> Result<(), ApiError> {
+        let suspended_at = Utc::now().naive_utc();
+        diesel::update(users::table.filter(users::id.eq(self.id)))
+            .set((
+                users::suspended_at.eq(suspended_at),
+                users::suspension_reason.eq(&reason),
+                users::suspended_until.eq(until),
+            ))
+            .execute(db)?;
+
+        self.suspended_at = Some(suspended_at);
+        self.suspension_reason = Some(reason);
+        self.suspended_until = until;
+        Ok(())
+    }
+
+    /// Lifts the suspension of the user
+    pub fn unsuspend(&mut self, db: &diesel::SqliteConnection) -> Result<(), ApiError> {
+        diesel::update(users::table.filter(users::id.eq(self.id)))
+            .set((
+                users::suspended_at.eq(None::<NaiveDateTime>),
+                users::suspension_reason.eq(None::<String>),
+                users::suspended_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(db)?;
+
+        self.suspended_at = None;
+        self.suspension_reason = None;
+        self.suspended_until = None;
+        Ok(())
+    }
 }
 
 #[derive(Insertable)]
@@ -59,82 +219,355 @@ impl User {
 pub struct InsertUser {
     /// The username of the `User` to be inserted
     pub username: String,
-    /// The ID of the `AuthProvider` that provides the authentication proof of the user
-    pub auth_provider: i32,
-    /// The *internal* token of the new user to communicate with our API
-    pub token: String,
-    /// The *external* token of the new user to communicate with the public API of said service
-    pub ext_token: String,
+    /// When the `User` registered
+    pub created_at: NaiveDateTime,
 }
 
 impl InsertUser {
     /// Creates a new instance of `InsertUser`, that Diesel will use to crate a given user
-    pub fn new(username: String, auth_provider: i32, ext_token: String) -> Self {
-        // Random generation of a token
-        let token = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(30)
-            .collect::<String>();
-
+    pub fn new(username: String) -> Self {
         InsertUser {
             username,
-            auth_provider,
-            token,
-            ext_token,
+            created_at: Utc::now().naive_utc(),
         }
     }
 }
 
+/// A user authenticated either by the token of a session, or by a personal access token
 pub struct APIUser {
     pub id: i32,
-    pub auth_provider: i32,
     pub username: String,
+    /// The ID of the `Session` used to authenticate the request,
+    /// unless it has been authenticated by a personal access token
+    pub session_id: Option<i32>,
+    /// What the request is allowed to do. Sessions are allowed everything
+    pub scopes: Vec<Scope>,
 }
 
 impl APIUser {
-    pub fn new_from_user(user: User) -> Self {
+    pub fn new_from_session(user: User, session: &Session) -> Self {
+        APIUser {
+            id: user.id.unwrap(),
+            username: user.username,
+            session_id: session.id,
+            scopes: Scope::all(),
+        }
+    }
+
+    pub fn new_from_access_token(user: User, access_token: &AccessToken) -> Self {
         APIUser {
             id: user.id.unwrap(),
-            auth_provider: user.auth_provider,
             username: user.username,
+            session_id: None,
+            scopes: access_token.get_scopes(),
         }
     }
+
+    /// Checks that the request has been granted the given scope
+    pub fn require_scope(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "The token lacks the {} scope",
+                scope.name()
+            )))
+        }
+    }
+
+    /// Checks that the request has been authenticated by a session, as managing the account
+    /// can't be done with personal access tokens. Returns the ID of the session
+    pub fn require_session(&self) -> Result<i32, ApiError> {
+        self.session_id.ok_or(ApiError::Forbidden(
+            "Personal access tokens can't manage the account".into(),
+        ))
+    }
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for APIUser {
-    type Error = &'a str;
+    type Error = ApiError;
 
     fn from_request(request: &'a Request<'r>) -> rocket::request::Outcome<Self, Self::Error> {
         let db = request.guard::<DatabaseConn>();
         match db {
             Outcome::Failure(_) => {
-                return Outcome::Failure((
-                    Status::InternalServerError,
-                    "Failed to connect to database",
-                ))
+                return guard_failure(
+                    request,
+                    ApiError::Database(format!("Failed to connect to database")),
+                )
             }
             Outcome::Forward(_) => {
-                return Outcome::Failure((Status::InternalServerError, "Got forwarded on DB query"))
+                return guard_failure(
+                    request,
+                    ApiError::Database(format!("Got forwarded on DB query")),
+                )
             }
             _ => (),
         };
 
-        let api_token: String = request
-            .cookies()
-            .get("api_token")
-            .unwrap_or(&Cookie::new("api_token", ""))
-            .value()
-            .into();
+        let api_token: String = match request_token(request) {
+            Ok(Some(token)) => token,
+            Ok(None) => return guard_failure(request, ApiError::NoCredentials),
+            Err(e) => return guard_failure(request, e),
+        };
+
+        let config = match request.guard::<State<GlobalConfig>>() {
+            Outcome::Success(config) => config,
+            _ => {
+                return guard_failure(
+                    request,
+                    ApiError::Internal(format!("Missing configuration")),
+                )
+            }
+        };
+        let hasher = config.borrow_security_config().get_token_hasher();
 
         let db: DatabaseConn = db.unwrap();
-        match User::find_by_token(api_token, &db) {
-            Ok(user) => Outcome::Success(APIUser::new_from_user(user)),
-            Err(_) => Outcome::Failure((Status::NotFound, "No user found")),
+
+        // Failing to record the activity of the token shouldn't prevent the request
+        if let Ok(session) = Session::find_valid(&api_token, &hasher, &db) {
+            let _ = session.touch(&db);
+            return match find_token_owner(session.user_id, &db) {
+                Ok(user) => Outcome::Success(APIUser::new_from_session(user, &session)),
+                Err(e) => guard_failure(request, e),
+            };
+        }
+
+        let access_token = match AccessToken::find_valid(&api_token, &hasher, &db) {
+            Ok(access_token) => access_token,
+            Err(_) => return guard_failure(request, ApiError::InvalidToken),
+        };
+        let _ = access_token.touch(&db);
+
+        match find_token_owner(access_token.user_id, &db) {
+            Ok(user) => Outcome::Success(APIUser::new_from_access_token(user, &access_token)),
+            Err(e) => guard_failure(request, e),
         }
     }
 }
 
+/// Finds the user owning a token, who mustn't be suspended
+fn find_token_owner(user_id: i32, db: &diesel::SqliteConnection) -> Result<User, ApiError> {
+    let user = User::find_by_id(user_id, db).map_err(|_| ApiError::InvalidToken)?;
+    user.check_not_suspended()?;
+    Ok(user)
+}
+
+/// Gets the API token given with the request.
+///
+/// The `Authorization: Bearer <token>` header takes precedence over the `api_token` cookie :
+/// when the header is present, the cookie is ignored, even if the header is malformed.
+fn request_token(request: &Request) -> Result<Option<String>, ApiError> {
+    if let Some(header) = request.headers().get_one("Authorization") {
+        let mut parts = header.splitn(2, ' ');
+        return match (parts.next(), parts.next()) {
+            (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("Bearer") => {
+                Ok(Some(token.trim().into()))
+            }
+            _ => Err(ApiError::InvalidToken),
+        };
+    }
+
+    Ok(request
+        .cookies()
+        .get("api_token")
+        .map(|cookie| cookie.value().into()))
+}
+
 #[get("/username")]
-pub fn get_username(api_user: APIUser) -> String {
-    api_user.username
+pub fn get_username(api_user: APIUser) -> Result<String, ApiError> {
+    api_user.require_scope(Scope::ReadProfile)?;
+    Ok(api_user.username)
+}
+
+/// Describes the profile of a user, as exposed by the API
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    /// The name to show, which is the username unless the user chose another one
+    pub display_name: String,
+    pub anonymous: bool,
+    pub public_link: Option<String>,
+    /// Name of the provider of the oldest identity of the user, which created the account
+    pub provider: Option<String>,
+    /// Address of the avatar of the user, taken from its identities
+    pub avatar_url: Option<String>,
+    /// Address of the profile page of the user on a provider, taken from its identities
+    pub profile_url: Option<String>,
+    /// Total number of stars earned by the user
+    pub stars: i64,
+    pub created_at: NaiveDateTime,
+    /// Roles granted to the user, such as `admin`
+    pub roles: Vec<Role>,
+}
+
+impl Profile {
+    /// Builds the profile of the given user, along with what its providers tell about it
+    pub fn for_user(user: User, db: &diesel::SqliteConnection) -> Result<Self, ApiError> {
+        // Identities come from the oldest to the newest
+        let identities = Identity::list_for_user(user.id.unwrap(), db)?;
+        let provider = identities.first().map(|(_, provider)| provider.clone());
+        let avatar_url = identities
+            .iter()
+            .filter_map(|(identity, _)| identity.avatar_url.clone())
+            .next();
+        let profile_url = identities
+            .iter()
+            .filter_map(|(identity, _)| identity.profile_url.clone())
+            .next();
+
+        let stars = Star::count_for_user(user.id.unwrap(), db)?;
+        let roles = UserRole::list_for_user(user.id.unwrap(), db)?;
+        let username = user.username;
+        Ok(Profile {
+            id: user.id.unwrap(),
+            display_name: user.display_name.unwrap_or_else(|| username.clone()),
+            username,
+            anonymous: user.anonymous,
+            public_link: user.public_link,
+            provider,
+            avatar_url,
+            profile_url,
+            stars,
+            created_at: user.created_at,
+            roles,
+        })
+    }
+}
+
+/// Changes to the profile of a user. Missing fields are left untouched
+#[derive(Deserialize)]
+pub struct ProfileUpdate {
+    /// An empty display name brings the username back
+    display_name: Option<String>,
+    anonymous: Option<bool>,
+    /// An empty link removes it
+    public_link: Option<String>,
+}
+
+/// Gets the profile of the logged in user
+#[get("/me")]
+pub fn get_me(api_user: APIUser, db: DatabaseConn) -> Result<Json<Profile>, ApiError> {
+    api_user.require_scope(Scope::ReadProfile)?;
+    let user = User::find_by_id(api_user.id, &db).map_err(|_| ApiError::NotFound)?;
+    Ok(Json(Profile::for_user(user, &db)?))
+}
+
+/// Edits the profile of the logged in user, and gives it back
+#[patch("/me", format = "json", data = "<update>")]
+pub fn update_me(
+    update: Json<ProfileUpdate>,
+    api_user: APIUser,
+    db: DatabaseConn,
+) -> Result<Json<Profile>, ApiError> {
+    api_user.require_session()?;
+    let mut user = User::find_by_id(api_user.id, &db).map_err(|_| ApiError::NotFound)?;
+    let update = update.into_inner();
+
+    let display_name = match update.display_name {
+        Some(display_name) => check_display_name(&display_name)?,
+        None => user.display_name.clone(),
+    };
+    let public_link = match update.public_link {
+        Some(public_link) => check_public_link(&public_link)?,
+        None => user.public_link.clone(),
+    };
+    let anonymous = update.anonymous.unwrap_or(user.anonymous);
+
+    user.set_profile(display_name, anonymous, public_link, &db)?;
+    Ok(Json(Profile::for_user(user, &db)?))
+}
+
+/// Checks a new display name. An empty one is removed
+fn check_display_name(display_name: &str) -> Result<Option<String>, ApiError> {
+    let display_name = display_name.trim();
+    if display_name.is_empty() {
+        return Ok(None);
+    }
+    if display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err(ApiError::Validation(format!(
+            "A display name has at most {} characters",
+            MAX_DISPLAY_NAME_LENGTH
+        )));
+    }
+    if display_name.chars().any(|c| c.is_control()) {
+        return Err(ApiError::Validation(
+            "A display name can't contain control characters".into(),
+        ));
+    }
+    Ok(Some(display_name.into()))
+}
+
+/// Checks a new public link, which has to be a web address. An empty one is removed
+fn check_public_link(public_link: &str) -> Result<Option<String>, ApiError> {
+    let public_link = public_link.trim();
+    if public_link.is_empty() {
+        return Ok(None);
+    }
+
+    let error = || ApiError::Validation("The public link has to be a web address".into());
+    let url = Url::parse(public_link).map_err(|_| error())?;
+    if (url.scheme() != "http" && url.scheme() != "https")
+        || public_link.len() > MAX_PUBLIC_LINK_LENGTH
+    {
+        return Err(error());
+    }
+    Ok(Some(url.into_string()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{check_display_name, check_public_link, User};
+    use chrono::{Duration, Utc};
+    use error::ApiError;
+
+    #[test]
+    pub fn check_profile_fields() {
+        assert_eq!(check_display_name("  Santa "), Ok(Some("Santa".into())));
+        assert_eq!(check_display_name(" "), Ok(None));
+        assert!(check_display_name(&"a".repeat(31)).is_err());
+        assert_eq!(
+            check_display_name("Santa\u{7}"),
+            Err(ApiError::Validation(
+                "A display name can't contain control characters".into()
+            ))
+        );
+
+        assert_eq!(
+            check_public_link("https://example.com/~santa"),
+            Ok(Some("https://example.com/~santa".into()))
+        );
+        assert_eq!(check_public_link(""), Ok(None));
+        assert!(check_public_link("javascript:alert(1)").is_err());
+        assert!(check_public_link("not a link").is_err());
+    }
+
+    #[test]
+    pub fn suspension_ends() {
+        let now = Utc::now().naive_utc();
+        let mut user = User {
+            id: Some(1),
+            username: "cheater".into(),
+            display_name: None,
+            anonymous: false,
+            public_link: None,
+            created_at: now,
+            suspended_at: None,
+            suspension_reason: None,
+            suspended_until: None,
+            deleted_at: None,
+        };
+        assert!(user.check_not_suspended().is_ok());
+
+        user.suspended_at = Some(now);
+        user.suspension_reason = Some("Leaderboard cheating".into());
+        assert!(user.is_suspended());
+
+        user.suspended_until = Some(now + Duration::days(7));
+        assert!(user.check_not_suspended().is_err());
+
+        user.suspended_until = Some(now - Duration::days(1));
+        assert!(!user.is_suspended());
+    }
 }
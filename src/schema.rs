@@ -1,3 +1,30 @@
+table! {
+    access_tokens (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        name -> Text,
+        token_prefix -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Nullable<Integer>,
+        event -> Text,
+        user_id -> Nullable<Integer>,
+        actor_id -> Nullable<Integer>,
+        provider -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        details -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     authprovider (id) {
         id -> Nullable<Integer>,
@@ -6,15 +33,127 @@ table! {
 }
 
 table! {
-    users (id) {
+    email_logins (id) {
         id -> Nullable<Integer>,
+        email -> Text,
+        token_prefix -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        nonce_hash -> Text,
+    }
+}
+
+table! {
+    identities (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        auth_provider -> Integer,
+        ext_id -> Nullable<Text>,
         username -> Text,
+        ext_token -> Nullable<Text>,
+        ext_refresh_token -> Nullable<Text>,
+        ext_token_expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        avatar_url -> Nullable<Text>,
+        profile_url -> Nullable<Text>,
+    }
+}
+
+table! {
+    legacy_tokens (session_id) {
+        session_id -> Integer,
         token -> Text,
-        auth_provider -> Integer,
-        ext_token -> Text,
     }
 }
 
-joinable!(users -> authprovider (auth_provider));
+table! {
+    local_credentials (id) {
+        id -> Nullable<Integer>,
+        identity_id -> Integer,
+        password_hash -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    login_codes (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        code_prefix -> Text,
+        code_hash -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        token_prefix -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        last_seen -> Timestamp,
+        expires_at -> Timestamp,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+table! {
+    stars (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        day -> Integer,
+        part -> Integer,
+        earned_at -> Timestamp,
+    }
+}
+
+table! {
+    user_roles (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        role -> Text,
+        granted_at -> Timestamp,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Nullable<Integer>,
+        username -> Text,
+        display_name -> Nullable<Text>,
+        anonymous -> Bool,
+        public_link -> Nullable<Text>,
+        created_at -> Timestamp,
+        suspended_at -> Nullable<Timestamp>,
+        suspension_reason -> Nullable<Text>,
+        suspended_until -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+joinable!(access_tokens -> users (user_id));
+joinable!(identities -> authprovider (auth_provider));
+joinable!(identities -> users (user_id));
+joinable!(legacy_tokens -> sessions (session_id));
+joinable!(local_credentials -> identities (identity_id));
+joinable!(login_codes -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(stars -> users (user_id));
+joinable!(user_roles -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(authprovider, users,);
+allow_tables_to_appear_in_same_query!(
+    access_tokens,
+    audit_log,
+    authprovider,
+    email_logins,
+    identities,
+    legacy_tokens,
+    local_credentials,
+    login_codes,
+    sessions,
+    stars,
+    user_roles,
+    users,
+);
@@ -0,0 +1,20 @@
+#[derive(Deserialize, Clone, Debug)]
+pub struct AdminConfig {
+    /// Name of the provider the first admin logs in with
+    provider: String,
+    /// Immutable ID of the first admin on this provider, such as the numeric ID of a Github
+    /// user, or the lowercase username of a local account. Usernames can be taken over
+    ext_id: String,
+}
+
+impl AdminConfig {
+    /// Gets the name of the provider of the first admin
+    pub fn get_provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Gets the immutable ID of the first admin on its provider
+    pub fn get_ext_id(&self) -> &str {
+        &self.ext_id
+    }
+}
@@ -0,0 +1,366 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// Prefix of the environment variables overriding the configuration
+const ENV_PREFIX: &str = "AOC18_";
+
+/// Environment variable giving the path of the configuration file
+pub const PATH_VAR: &str = "AOC18_CONFIG";
+
+/// Path of the configuration file when none is given
+const DEFAULT_PATH: &str = "config/config.toml";
+
+/// Separates the keys of a setting in the name of an environment variable
+const KEY_SEPARATOR: &str = "__";
+
+/// Suffix of the settings read from a file
+const FILE_SUFFIX: &str = "_file";
+
+/// Kinds of providers that used to be configured in top-level tables
+const LEGACY_PROVIDERS: &[&str] = &["github", "gitlab", "oidc"];
+
+/// Finds the configuration file, given by `--config <path>` or `--config=<path>`, else by the
+/// `AOC18_CONFIG` environment variable, else `config/config.toml`.
+/// Returns the path along with whether it has been explicitly given
+pub fn find_path(args: &[String], env_path: Option<String>) -> Result<(PathBuf, bool), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return match args.next() {
+                Some(path) => Ok((path.into(), true)),
+                None => Err("--config expects the path of the configuration file".into()),
+            };
+        }
+        if arg.starts_with("--config=") {
+            return Ok((arg["--config=".len()..].into(), true));
+        }
+    }
+
+    match env_path {
+        Some(path) => Ok((path.into(), true)),
+        None => Ok((DEFAULT_PATH.into(), false)),
+    }
+}
+
+/// Reads the configuration file. A missing file is only an error when it has been explicitly
+/// given, as the whole configuration may come from the environment
+pub fn read_file(path: &Path, explicit: bool) -> Result<Value, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound && !explicit => {
+            return Ok(Value::Table(Table::new()))
+        }
+        Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+    };
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    content
+        .parse::<Value>()
+        .map_err(|e| format!("{} is not valid TOML: {}", path.display(), e))
+}
+
+/// Applies the overrides given by the environment variables, such as `AOC18_DATABASE__URL`
+/// for `url` in the `[database]` table. Instances of a provider listed as `[[providers.oidc]]`
+/// are told apart by their index, as in `AOC18_PROVIDERS__OIDC__0__SECRET`.
+///
+/// Values are read as TOML when possible, so that `25` or `true` keep their type, and can be
+/// quoted (`"25"`) to be kept as strings. Values replacing a string are always strings.
+pub fn apply_env<I>(config: &mut Value, vars: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (var, raw) in vars {
+        if !var.starts_with(ENV_PREFIX) || var == PATH_VAR {
+            continue;
+        }
+
+        let keys: Vec<String> = var[ENV_PREFIX.len()..]
+            .split(KEY_SEPARATOR)
+            .map(|key| key.to_lowercase())
+            .collect();
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(format!("{} doesn't name a setting", var));
+        }
+        set_value(config, &keys, &raw).map_err(|e| format!("{}: {}", var, e))?;
+    }
+    Ok(())
+}
+
+/// Sets the setting found by following the given keys
+fn set_value(target: &mut Value, keys: &[String], raw: &str) -> Result<(), String> {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    match *target {
+        Value::Table(ref mut table) => {
+            if rest.is_empty() {
+                let value = parse_value(raw, table.get(key));
+                table.insert(key.clone(), value);
+                return Ok(());
+            }
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            set_value(entry, rest, raw)
+        }
+        Value::Array(ref mut values) => {
+            let count = values.len();
+            let entry = key
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| values.get_mut(index))
+                .ok_or(format!(
+                    "{} isn't the index of one of the {} entries",
+                    key, count
+                ))?;
+            if rest.is_empty() {
+                let value = parse_value(raw, Some(&*entry));
+                *entry = value;
+                return Ok(());
+            }
+            set_value(entry, rest, raw)
+        }
+        _ => Err(format!("{} can't hold a setting", key)),
+    }
+}
+
+/// Reads the value of an environment variable, replacing the given one if any
+fn parse_value(raw: &str, replaced: Option<&Value>) -> Value {
+    if let Some(&Value::String(_)) = replaced {
+        return Value::String(raw.into());
+    }
+
+    format!("value = {}", raw)
+        .parse::<Value>()
+        .ok()
+        .and_then(|parsed| parsed.get("value").cloned())
+        .unwrap_or_else(|| Value::String(raw.into()))
+}
+
+/// Moves the providers still configured in the top-level `[github]`, `[gitlab]` and `[oidc]`
+/// tables, or by variables such as `AOC18_GITHUB__SECRET`, to the `[providers]` table where
+/// they now belong. Their settings override the ones of `[providers.<kind>]`, unless it lists
+/// several instances, which can't tell which one is meant
+pub fn move_legacy_providers(config: &mut Value) -> Result<(), String> {
+    let table = match *config {
+        Value::Table(ref mut table) => table,
+        _ => return Ok(()),
+    };
+
+    for kind in LEGACY_PROVIDERS {
+        let legacy = match table.remove(*kind) {
+            Some(legacy) => legacy,
+            None => continue,
+        };
+        println!("[{}] is deprecated, and read as [providers.{}]", kind, kind);
+
+        let providers = match *table
+            .entry("providers".to_string())
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(ref mut providers) => providers,
+            _ => return Err("providers has to be a table".into()),
+        };
+        if !providers.contains_key(*kind) {
+            providers.insert(kind.to_string(), legacy);
+            continue;
+        }
+        match (providers.get_mut(*kind), legacy) {
+            (Some(&mut Value::Table(ref mut current)), Value::Table(legacy)) => {
+                current.extend(legacy)
+            }
+            _ => {
+                return Err(format!(
+                    "[{}] has moved to [providers.{}], and can't be merged with it",
+                    kind, kind
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces every `<key>_file` setting by `<key>`, holding the content of the given file.
+/// Secrets can thus be mounted as files rather than written in the configuration
+pub fn resolve_files(config: &mut Value, path: &str) -> Result<(), String> {
+    match *config {
+        Value::Table(ref mut table) => {
+            let file_keys: Vec<String> = table
+                .keys()
+                .filter(|key| key.len() > FILE_SUFFIX.len() && key.ends_with(FILE_SUFFIX))
+                .cloned()
+                .collect();
+            for file_key in file_keys {
+                let key = file_key[..file_key.len() - FILE_SUFFIX.len()].to_string();
+                if table.contains_key(&key) {
+                    return Err(format!(
+                        "{} and {} can't be both set",
+                        join(path, &key),
+                        join(path, &file_key)
+                    ));
+                }
+
+                let file = match table.remove(&file_key) {
+                    Some(Value::String(file)) => file,
+                    _ => return Err(format!("{} has to be a path", join(path, &file_key))),
+                };
+                let mut content = String::new();
+                File::open(&file)
+                    .and_then(|mut f| f.read_to_string(&mut content))
+                    .map_err(|e| {
+                        format!("{}: failed to read {}: {}", join(path, &file_key), file, e)
+                    })?;
+                // Files usually end with a newline, which isn't part of the secret
+                let content = content.trim_end_matches(|c: char| c == '\n' || c == '\r');
+                table.insert(key, Value::String(content.into()));
+            }
+
+            for (key, value) in table.iter_mut() {
+                resolve_files(value, &join(path, key))?;
+            }
+            Ok(())
+        }
+        Value::Array(ref mut values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                resolve_files(value, &format!("{}[{}]", path, i))?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Gets the full name of a setting
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.into()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{apply_env, find_path, move_legacy_providers, resolve_files};
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use toml::Value;
+
+    #[test]
+    pub fn find_config_path() {
+        let args = vec!["--config".to_string(), "prod.toml".to_string()];
+        assert_eq!(
+            find_path(&args, Some("env.toml".into())),
+            Ok((PathBuf::from("prod.toml"), true))
+        );
+        assert_eq!(
+            find_path(&["--config=prod.toml".to_string()], None),
+            Ok((PathBuf::from("prod.toml"), true))
+        );
+        assert_eq!(
+            find_path(&[], Some("env.toml".into())),
+            Ok((PathBuf::from("env.toml"), true))
+        );
+        assert_eq!(
+            find_path(&[], None),
+            Ok((PathBuf::from("config/config.toml"), false))
+        );
+        assert!(find_path(&["--config".to_string()], None).is_err());
+    }
+
+    #[test]
+    pub fn override_settings() {
+        let mut config: Value = "[providers.github]\nclient_id = \"id\"\nsecret = \"old\"\n\
+                                 [[providers.oidc]]\nissuer = \"https://example.com\"\n"
+            .parse()
+            .expect("Valid TOML");
+        let vars = vec![
+            (
+                "AOC18_PROVIDERS__GITHUB__SECRET".to_string(),
+                "12345".to_string(),
+            ),
+            ("AOC18_LOCAL__REGISTRATION".to_string(), "false".to_string()),
+            (
+                "AOC18_PROVIDERS__OIDC__0__CLIENT_ID".to_string(),
+                "client".to_string(),
+            ),
+            ("AOC18_CONFIG".to_string(), "ignored.toml".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        apply_env(&mut config, vars).expect("Valid overrides");
+
+        // The secret replaces a string, so it is kept as one
+        assert_eq!(
+            config["providers"]["github"]["secret"].as_str(),
+            Some("12345")
+        );
+        assert_eq!(config["local"]["registration"].as_bool(), Some(false));
+        assert_eq!(
+            config["providers"]["oidc"][0]["client_id"].as_str(),
+            Some("client")
+        );
+        assert!(config.get("config").is_none());
+
+        let invalid = vec![(
+            "AOC18_PROVIDERS__OIDC__1__CLIENT_ID".to_string(),
+            "x".to_string(),
+        )];
+        assert!(apply_env(&mut config, invalid).is_err());
+    }
+
+    #[test]
+    pub fn read_secrets_from_files() {
+        let secret_path = env::temp_dir().join("aoc18_config_secret");
+        File::create(&secret_path)
+            .and_then(|mut file| file.write_all(b"s3cr3t\n"))
+            .expect("Secret file written");
+
+        let mut config: Value = format!(
+            "[security]\ntoken_key_file = \"{}\"\n",
+            secret_path.display()
+        )
+        .parse()
+        .expect("Valid TOML");
+        resolve_files(&mut config, "").expect("Readable secret");
+        assert_eq!(config["security"]["token_key"].as_str(), Some("s3cr3t"));
+        assert!(config["security"].get("token_key_file").is_none());
+
+        let mut config: Value = "[security]\ntoken_key = \"a\"\ntoken_key_file = \"b\"\n"
+            .parse()
+            .expect("Valid TOML");
+        assert!(resolve_files(&mut config, "").is_err());
+    }
+
+    #[test]
+    pub fn move_legacy_providers_tables() {
+        let mut config: Value = "[github]\nclient_id = \"id\"\nsecret = \"s\"\n\
+                                 [providers.gitlab]\nclient_id = \"id\"\n\
+                                 [[providers.oidc]]\nissuer = \"https://example.com\"\n"
+            .parse()
+            .expect("Valid TOML");
+        let vars = vec![("AOC18_GITLAB__SECRET".to_string(), "12345".to_string())];
+        apply_env(&mut config, vars).expect("Valid overrides");
+        move_legacy_providers(&mut config).expect("Movable providers");
+
+        assert_eq!(config["providers"]["github"]["secret"].as_str(), Some("s"));
+        assert_eq!(
+            config["providers"]["gitlab"]["secret"].as_str(),
+            Some("12345")
+        );
+        assert!(config.get("github").is_none());
+        assert!(config.get("gitlab").is_none());
+
+        // Several instances can't be told apart
+        let vars = vec![("AOC18_OIDC__SECRET".to_string(), "12345".to_string())];
+        apply_env(&mut config, vars).expect("Valid overrides");
+        assert!(move_legacy_providers(&mut config).is_err());
+    }
+}
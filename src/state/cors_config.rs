@@ -0,0 +1,126 @@
+use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions, Method};
+use std::str::FromStr;
+
+/// Origins, methods and headers allowed in cross-origin requests to the API
+#[derive(Deserialize, Debug)]
+pub struct CorsConfig {
+    /// Origins allowed as they are (e.g. `https://example.com`), or `*` to allow any origin
+    #[serde(default = "default_origins")]
+    origins: Vec<String>,
+    /// Regular expressions matching the other allowed origins (e.g. `^https://\w+\.example\.com$`)
+    #[serde(default)]
+    origin_patterns: Vec<String>,
+    /// HTTP methods allowed
+    #[serde(default = "default_methods")]
+    methods: Vec<String>,
+    /// Headers allowed in the requests, or `*` to allow any header
+    #[serde(default = "default_headers")]
+    headers: Vec<String>,
+    /// Whether the requests can carry cookies, such as the session token
+    #[serde(default = "default_credentials")]
+    credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            origins: default_origins(),
+            origin_patterns: Vec::new(),
+            methods: default_methods(),
+            headers: default_headers(),
+            credentials: default_credentials(),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Builds the `Cors` fairing enforcing this policy.
+    /// Fails with a message naming the invalid setting
+    pub fn get_cors(&self) -> Result<Cors, String> {
+        let allowed_origins = if self.origins.iter().any(|origin| origin == "*") {
+            AllowedOrigins::all()
+        } else {
+            AllowedOrigins::some(&self.origins, &self.origin_patterns)
+        };
+
+        let allowed_methods = self
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase())
+                    .map_err(|_| format!("cors.methods: unknown method {}", method))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let allowed_headers = if self.headers.iter().any(|header| header == "*") {
+            AllowedHeaders::all()
+        } else {
+            let headers: Vec<&str> = self.headers.iter().map(|header| header.as_str()).collect();
+            AllowedHeaders::some(&headers)
+        };
+
+        CorsOptions {
+            allowed_origins: allowed_origins,
+            allowed_methods: allowed_methods,
+            allowed_headers: allowed_headers,
+            allow_credentials: self.credentials,
+            ..Default::default()
+        }
+        .to_cors()
+        .map_err(|e| format!("cors.origins or cors.origin_patterns: {}", e))
+    }
+}
+
+fn default_origins() -> Vec<String> {
+    vec!["http://localhost".into()]
+}
+
+fn default_methods() -> Vec<String> {
+    vec![
+        "GET".into(),
+        "POST".into(),
+        "PUT".into(),
+        "PATCH".into(),
+        "DELETE".into(),
+    ]
+}
+
+fn default_headers() -> Vec<String> {
+    vec![
+        "Authorization".into(),
+        "Accept".into(),
+        "Content-Type".into(),
+    ]
+}
+
+fn default_credentials() -> bool {
+    true
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::CorsConfig;
+
+    #[test]
+    pub fn build_cors_policy() {
+        assert!(CorsConfig::default().get_cors().is_ok());
+
+        let config = CorsConfig {
+            origin_patterns: vec![r"^https://\w+\.example\.com$".into()],
+            ..CorsConfig::default()
+        };
+        assert!(config.get_cors().is_ok());
+
+        let config = CorsConfig {
+            methods: vec!["FETCH".into()],
+            ..CorsConfig::default()
+        };
+        assert!(config.get_cors().is_err());
+
+        let config = CorsConfig {
+            origin_patterns: vec!["(".into()],
+            ..CorsConfig::default()
+        };
+        assert!(config.get_cors().is_err());
+    }
+}
@@ -1,4 +1,70 @@
-#[derive(Serialize, Deserialize, Debug)]
+use db::{Pragmas, DATABASE_NAME};
+use rocket::config::{Table, Value};
+
+/// The SQLite database, along with the pool of connections to it
+#[derive(Deserialize, Debug)]
 pub struct DatabaseConfig {
+    /// Path of the database file
     url: String,
+    /// Number of connections in the pool. Defaults to the number of Rocket workers
+    pool_size: Option<u32>,
+    /// Milliseconds to wait for a lock held by another connection before failing
+    #[serde(default = "default_busy_timeout")]
+    busy_timeout: u32,
+    /// Whether the database uses a write-ahead log, letting readers go along with a writer
+    #[serde(default)]
+    wal: bool,
+    /// Whether the foreign keys are enforced
+    #[serde(default)]
+    foreign_keys: bool,
+}
+
+impl DatabaseConfig {
+    /// Gets the path of the database file
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    /// Gets the pragmas to set on every connection
+    pub fn get_pragmas(&self) -> Pragmas {
+        Pragmas {
+            busy_timeout: Some(self.busy_timeout),
+            wal: self.wal,
+            foreign_keys: self.foreign_keys,
+        }
+    }
+
+    /// Checks the settings that can be wrong regardless of the database itself
+    pub fn check(&self) -> Result<(), String> {
+        if self.url.trim().is_empty() {
+            return Err("database.url can't be empty".into());
+        }
+        if self.pool_size == Some(0) {
+            return Err("database.pool_size has to be at least 1".into());
+        }
+        Ok(())
+    }
+
+    /// Describes the pool of `DatabaseConn` as the `databases` table of the Rocket configuration
+    pub fn get_rocket_databases(&self) -> Value {
+        let mut pool = Table::new();
+        pool.insert("url".into(), Value::String(self.url.clone()));
+        if let Some(pool_size) = self.pool_size {
+            pool.insert("pool_size".into(), Value::Integer(pool_size.into()));
+        }
+        pool.insert(
+            "busy_timeout".into(),
+            Value::Integer(self.busy_timeout.into()),
+        );
+        pool.insert("wal".into(), Value::Boolean(self.wal));
+        pool.insert("foreign_keys".into(), Value::Boolean(self.foreign_keys));
+
+        let mut databases = Table::new();
+        databases.insert(DATABASE_NAME.into(), Value::Table(pool));
+        Value::Table(databases)
+    }
+}
+
+fn default_busy_timeout() -> u32 {
+    5000
 }
@@ -0,0 +1,145 @@
+use mail::file::FileSender;
+use mail::log::LogSender;
+use mail::smtp::SmtpSender;
+use mail::MailSender;
+use std::fmt;
+
+#[derive(Deserialize, Debug)]
+pub struct EmailAuth {
+    /// Name of the `AuthProvider` the email identities belong to
+    #[serde(default = "default_name")]
+    name: String,
+    /// Address the login links are sent from
+    from: String,
+    /// Address of the page verifying a login link, to which its token is appended
+    link_url: String,
+    /// Address to redirect to after a login attempt
+    redirect: String,
+    /// Number of minutes a login link stays valid
+    #[serde(default = "default_link_lifetime")]
+    link_lifetime: i64,
+    /// How the login links are delivered
+    sender: SenderConfig,
+}
+
+/// Describes how mails are delivered
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SenderConfig {
+    /// Through a SMTP server
+    Smtp {
+        host: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+        /// Whether to use STARTTLS on the submission port, ignoring `port`
+        #[serde(default)]
+        tls: bool,
+    },
+    /// Written to files of a directory, for development
+    File { directory: String },
+    /// Printed on the standard output, for development
+    Log,
+}
+
+// The password is left out, so that logging the configuration doesn't leak it
+impl fmt::Debug for SenderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SenderConfig::Smtp {
+                ref host,
+                port,
+                ref username,
+                ref password,
+                tls,
+            } => f
+                .debug_struct("Smtp")
+                .field("host", host)
+                .field("port", &port)
+                .field("username", username)
+                .field("password", &password.as_ref().map(|_| "<redacted>"))
+                .field("tls", &tls)
+                .finish(),
+            SenderConfig::File { ref directory } => f
+                .debug_struct("File")
+                .field("directory", directory)
+                .finish(),
+            SenderConfig::Log => f.write_str("Log"),
+        }
+    }
+}
+
+impl EmailAuth {
+    /// Gets the name of the `AuthProvider` of the email identities
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the address of the page verifying a login link
+    pub fn get_link_url(&self) -> &str {
+        &self.link_url
+    }
+
+    /// Gets the address to redirect to after a login attempt
+    pub fn get_redirect(&self) -> &str {
+        &self.redirect
+    }
+
+    /// Gets the number of minutes a login link stays valid
+    pub fn get_link_lifetime(&self) -> i64 {
+        self.link_lifetime
+    }
+
+    /// Gets the configured way of delivering mails
+    pub fn get_sender(&self) -> Box<dyn MailSender> {
+        match self.sender {
+            SenderConfig::Smtp {
+                ref host,
+                port,
+                ref username,
+                ref password,
+                tls,
+            } => {
+                let credentials = match (username, password) {
+                    (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+                    _ => None,
+                };
+                Box::new(SmtpSender::new(host, port, credentials, tls, &self.from))
+            }
+            SenderConfig::File { ref directory } => {
+                Box::new(FileSender::new(directory, &self.from))
+            }
+            SenderConfig::Log => Box::new(LogSender),
+        }
+    }
+}
+
+fn default_name() -> String {
+    "email".into()
+}
+
+fn default_link_lifetime() -> i64 {
+    15
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::SenderConfig;
+
+    #[test]
+    pub fn redact_smtp_password() {
+        let sender: SenderConfig = toml::from_str(
+            "type = \"smtp\"\nhost = \"smtp.example.com\"\n\
+             username = \"santa\"\npassword = \"smtp_secret\"\n",
+        )
+        .expect("Valid sender config");
+        let debug = format!("{:?}", sender);
+        assert!(debug.contains("santa"));
+        assert!(!debug.contains("smtp_secret"));
+    }
+}
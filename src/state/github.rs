@@ -1,5 +1,14 @@
 #[derive(Deserialize, Debug)]
 pub struct GithubAuth {
+    /// Name of this Github instance, used in the login routes and as the `AuthProvider` name
+    #[serde(default = "default_name")]
+    name: String,
+    /// Base address of the Github instance, e.g. a Github Enterprise server
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// Base address of the API of the Github instance
+    #[serde(default = "default_api_url")]
+    api_url: String,
     /// Client ID of the Github app
     client_id: String,
     /// Secret identifier of the Github app
@@ -9,6 +18,21 @@ pub struct GithubAuth {
 }
 
 impl GithubAuth {
+    /// Gets the name of this Github instance
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the base address of the Github instance, without trailing slash
+    pub fn get_base_url(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+
+    /// Gets the base address of the API of the Github instance, without trailing slash
+    pub fn get_api_url(&self) -> &str {
+        self.api_url.trim_end_matches('/')
+    }
+
     /// Gets the client ID from the config
     pub fn get_client_id(&self) -> &str {
         &self.client_id
@@ -24,3 +48,15 @@ impl GithubAuth {
         &self.redirect
     }
 }
+
+fn default_name() -> String {
+    "github".into()
+}
+
+fn default_base_url() -> String {
+    "https://github.com".into()
+}
+
+fn default_api_url() -> String {
+    "https://api.github.com".into()
+}
@@ -1,8 +1,16 @@
 #[derive(Deserialize, Debug)]
 pub struct GitlabAuth {
-    /// Client ID of the Github app
+    /// Name of this Gitlab instance, used in the login routes and as the `AuthProvider` name
+    #[serde(default = "default_name")]
+    name: String,
+    /// Base address of the Gitlab instance, e.g. a self-hosted server
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// Base address of the API of the Gitlab instance. Defaults to the one of `base_url`
+    api_url: Option<String>,
+    /// Client ID of the GitLab application
     client_id: String,
-    /// Secret identifier of the Github app
+    /// Secret identifier of the GitLab application
     secret: String,
     /// Address to redirect to after a login attempt
     redirect: String,
@@ -11,12 +19,30 @@ pub struct GitlabAuth {
 }
 
 impl GitlabAuth {
+    /// Gets the name of this Gitlab instance
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the base address of the Gitlab instance, without trailing slash
+    pub fn get_base_url(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+
+    /// Gets the base address of the API of the Gitlab instance, without trailing slash
+    pub fn get_api_url(&self) -> String {
+        match self.api_url {
+            Some(ref api_url) => api_url.trim_end_matches('/').into(),
+            None => format!("{}/api/v4", self.get_base_url()),
+        }
+    }
+
     /// Gets the client ID from the config
     pub fn get_client_id(&self) -> &str {
         &self.client_id
     }
 
-    /// Gets the secret identifier of the Github app from the config
+    /// Gets the secret identifier of the GitLab application from the config
     pub fn get_secret(&self) -> &str {
         &self.secret
     }
@@ -31,3 +57,11 @@ impl GitlabAuth {
         &self.redirect_api
     }
 }
+
+fn default_name() -> String {
+    "gitlab".into()
+}
+
+fn default_base_url() -> String {
+    "https://gitlab.com".into()
+}
@@ -1,41 +1,234 @@
+use login::provider::OAuthProvider;
+use serde::{Deserialize, Deserializer};
+use state::admin_config::AdminConfig;
+use state::config_source::{self, PATH_VAR};
+use state::cors_config::CorsConfig;
 use state::database_config::DatabaseConfig;
+use state::email_auth::EmailAuth;
 use state::github::GithubAuth;
 use state::gitlab::GitlabAuth;
-use std::fs::File;
-use std::io::Read;
-use toml::de::from_slice;
+use state::local_auth::LocalAuth;
+use state::oidc::OidcAuth;
+use state::security_config::SecurityConfig;
+use std::env;
+
+/// Names taken by the static login routes, such as `/login/exchange`, which OAUTH providers thus
+/// can't use for their own `/login/<provider>` routes
+const RESERVED_NAMES: [&str; 4] = ["providers", "exchange", "email", "local"];
 
 #[derive(Deserialize, Debug)]
 pub struct GlobalConfig {
-    github: GithubAuth,
-    gitlab: GitlabAuth,
+    /// OAUTH providers users can log in with. None of them is required
+    #[serde(default)]
+    providers: ProvidersConfig,
+    /// Local accounts, with a username and a password, if enabled
+    local: Option<LocalAuth>,
+    /// Passwordless logins through links sent by email, if enabled
+    email: Option<EmailAuth>,
+    /// The user to make the first admin, if no one is yet
+    admin: Option<AdminConfig>,
+    /// Policy of the cross-origin requests to the API
+    #[serde(default)]
+    cors: CorsConfig,
     database: DatabaseConfig,
+    security: SecurityConfig,
+}
+
+/// Configured OAUTH providers, by kind
+#[derive(Deserialize, Debug, Default)]
+pub struct ProvidersConfig {
+    /// Github instances, either a single `[providers.github]` table or several
+    /// `[[providers.github]]` ones
+    #[serde(default, deserialize_with = "one_or_many")]
+    github: Vec<GithubAuth>,
+    /// Gitlab instances, either a single `[providers.gitlab]` table or several
+    /// `[[providers.gitlab]]` ones
+    #[serde(default, deserialize_with = "one_or_many")]
+    gitlab: Vec<GitlabAuth>,
+    /// OpenID Connect providers, either a single `[providers.oidc]` table or several
+    /// `[[providers.oidc]]` ones
+    #[serde(default, deserialize_with = "one_or_many")]
+    oidc: Vec<OidcAuth>,
 }
 
 impl GlobalConfig {
-    /// Loads the configuration, searching for a valid TOML file in config/config.toml
-    pub fn load() -> GlobalConfig {
-        let mut config_file =
-            File::open("config/config.toml").expect("No config file in config/config.toml");
-        let mut buf: Vec<u8> = Vec::new();
-        config_file
-            .read_to_end(&mut buf)
-            .expect("Failed to read config file");
-        from_slice(&buf).expect("Config file not formatted correctly")
+    /// Loads the configuration in layers, each one overriding the previous ones:
+    /// - the defaults of the optional settings
+    /// - the TOML file given by `--config <path>` or `AOC18_CONFIG`, else `config/config.toml`
+    /// - the `AOC18_SECTION__KEY` environment variables
+    ///
+    /// Providers still configured in the top-level `[github]`, `[gitlab]` and `[oidc]` tables
+    /// are moved to `[providers]`. Any `<key>_file` setting is then replaced by `<key>`, read
+    /// from the given file, so that secrets can be mounted rather than written down. Fails with
+    /// a message naming the offending setting
+    pub fn load() -> Result<GlobalConfig, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let (path, explicit) = config_source::find_path(&args, env::var(PATH_VAR).ok())?;
+        let mut value = config_source::read_file(&path, explicit)?;
+        config_source::apply_env(&mut value, env::vars())?;
+        config_source::move_legacy_providers(&mut value)?;
+        config_source::resolve_files(&mut value, "")?;
+
+        // Going through the TOML text gets errors naming the key they are about
+        let text = toml::to_string(&value).map_err(|e| format!("Invalid configuration: {}", e))?;
+        let config: GlobalConfig =
+            toml::from_str(&text).map_err(|e| format!("Invalid configuration: {}", e))?;
+
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Checks the settings that can't be checked on their own
+    fn check(&self) -> Result<(), String> {
+        let github = self
+            .providers
+            .github
+            .iter()
+            .map(|p| ("providers.github", p.get_name()));
+        let gitlab = self
+            .providers
+            .gitlab
+            .iter()
+            .map(|p| ("providers.gitlab", p.get_name()));
+        let oidc = self
+            .providers
+            .oidc
+            .iter()
+            .map(|p| ("providers.oidc", p.get_name()));
+        for (key, name) in github.chain(gitlab).chain(oidc) {
+            if RESERVED_NAMES.contains(&name) || name.contains('/') {
+                return Err(format!(
+                    "{}.name can't be {}, as it would clash with the login routes",
+                    key, name
+                ));
+            }
+        }
+
+        // The ID tokens carrying the identity of the users are only issued for the openid scope
+        for oidc in &self.providers.oidc {
+            if !oidc.get_scopes().contains(&"openid") {
+                return Err(format!(
+                    "providers.oidc.scopes of {} has to contain openid",
+                    oidc.get_name()
+                ));
+            }
+        }
+
+        // Providers are told apart by their name, which thus has to be unique
+        let providers = self.providers();
+        let mut names: Vec<&str> = providers.iter().map(|p| p.name()).collect();
+        names.extend(self.local.as_ref().map(|local| local.get_name()));
+        names.extend(self.email.as_ref().map(|email| email.get_name()));
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(format!("Several providers are named {}", name));
+            }
+        }
+
+        // Anyone could register the username of the first admin while registration is open
+        if let (Some(admin), Some(local)) = (self.admin.as_ref(), self.local.as_ref()) {
+            if admin.get_provider() == local.get_name() && local.is_registration_open() {
+                return Err(format!(
+                    "admin.provider can't be the local accounts ({}) unless local.registration \
+                     is false",
+                    local.get_name()
+                ));
+            }
+        }
+
+        self.database.check()?;
+        self.security
+            .get_token_cipher()
+            .map_err(|e| format!("Invalid security.ext_token_keys: {}", e))?;
+        self.cors
+            .get_cors()
+            .map(|_| ())
+            .map_err(|e| format!("Invalid CORS policy: {}", e))
     }
 
     /// Gets a borrow to the github part of the configuration
-    pub fn borrow_github_config(&self) -> &GithubAuth {
-        &self.github
+    pub fn borrow_github_config(&self) -> &[GithubAuth] {
+        &self.providers.github
     }
 
     /// Gets a borrow to the gitlab part of the configuration
-    pub fn borrow_gitlab_config(&self) -> &GitlabAuth {
-        &self.gitlab
+    pub fn borrow_gitlab_config(&self) -> &[GitlabAuth] {
+        &self.providers.gitlab
+    }
+
+    /// Gets a borrow to the OpenID Connect part of the configuration
+    pub fn borrow_oidc_config(&self) -> &[OidcAuth] {
+        &self.providers.oidc
+    }
+
+    /// Gets a borrow to the local accounts part of the configuration, if they are enabled
+    pub fn borrow_local_config(&self) -> Option<&LocalAuth> {
+        self.local.as_ref()
+    }
+
+    /// Gets a borrow to the email logins part of the configuration, if they are enabled
+    pub fn borrow_email_config(&self) -> Option<&EmailAuth> {
+        self.email.as_ref()
+    }
+
+    /// Gets a borrow to the first admin part of the configuration, if any
+    pub fn borrow_admin_config(&self) -> Option<&AdminConfig> {
+        self.admin.as_ref()
+    }
+
+    /// Gets a borrow to the CORS part of the configuration
+    pub fn borrow_cors_config(&self) -> &CorsConfig {
+        &self.cors
+    }
+
+    /// Finds a configured OAUTH provider by its name
+    pub fn find_provider(&self, name: &str) -> Option<&dyn OAuthProvider> {
+        self.providers().into_iter().find(|p| p.name() == name)
+    }
+
+    /// Lists every configured OAUTH provider
+    pub fn providers(&self) -> Vec<&dyn OAuthProvider> {
+        let github = self
+            .providers
+            .github
+            .iter()
+            .map(|p| p as &dyn OAuthProvider);
+        let gitlab = self
+            .providers
+            .gitlab
+            .iter()
+            .map(|p| p as &dyn OAuthProvider);
+        let oidc = self.providers.oidc.iter().map(|p| p as &dyn OAuthProvider);
+        github.chain(gitlab).chain(oidc).collect()
     }
 
     /// Gets a borrow to the database part of the configuration
     pub fn borrow_database_config(&self) -> &DatabaseConfig {
         &self.database
     }
+
+    /// Gets a borrow to the security part of the configuration
+    pub fn borrow_security_config(&self) -> &SecurityConfig {
+        &self.security
+    }
+}
+
+/// Either a single value or a list of values
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// Deserializes either a single value or a list of values into a list
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
 }
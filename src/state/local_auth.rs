@@ -0,0 +1,41 @@
+#[derive(Deserialize, Debug)]
+pub struct LocalAuth {
+    /// Name of the `AuthProvider` the local accounts belong to
+    #[serde(default = "default_name")]
+    name: String,
+    /// Whether anyone can create a local account
+    #[serde(default = "default_registration")]
+    registration: bool,
+    /// Minimal number of characters of a password
+    #[serde(default = "default_min_password_length")]
+    min_password_length: usize,
+}
+
+impl LocalAuth {
+    /// Gets the name of the `AuthProvider` of the local accounts
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Checks whether anyone can create a local account
+    pub fn is_registration_open(&self) -> bool {
+        self.registration
+    }
+
+    /// Gets the minimal number of characters of a password
+    pub fn get_min_password_length(&self) -> usize {
+        self.min_password_length
+    }
+}
+
+fn default_name() -> String {
+    "local".into()
+}
+
+fn default_registration() -> bool {
+    true
+}
+
+fn default_min_password_length() -> usize {
+    10
+}
@@ -1,4 +1,11 @@
+pub mod admin_config;
+pub mod config_source;
+pub mod cors_config;
 pub mod database_config;
+pub mod email_auth;
 pub mod github;
 pub mod gitlab;
 pub mod global_config;
+pub mod local_auth;
+pub mod oidc;
+pub mod security_config;
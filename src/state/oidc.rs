@@ -0,0 +1,84 @@
+use login::oidc::{KeyCache, ProviderMetadata};
+use std::sync::Mutex;
+
+#[derive(Deserialize, Debug)]
+pub struct OidcAuth {
+    /// Name of this OpenID Connect provider, used in the login routes and as the `AuthProvider` name
+    #[serde(default = "default_name")]
+    name: String,
+    /// Issuer of the provider, whose metadata is published at
+    /// `<issuer>/.well-known/openid-configuration`
+    issuer: String,
+    /// Client ID of the OpenID Connect client
+    client_id: String,
+    /// Secret of the OpenID Connect client
+    secret: String,
+    /// Address to redirect to after a login attempt
+    redirect: String,
+    /// Address of our callback, as registered on the provider
+    redirect_api: String,
+    /// Scopes requested from the provider
+    #[serde(default = "default_scopes")]
+    scopes: Vec<String>,
+    /// Metadata of the provider, discovered on first use
+    #[serde(skip)]
+    metadata: Mutex<Option<ProviderMetadata>>,
+    /// Keys signing the ID tokens of the provider, fetched on first use
+    #[serde(skip)]
+    keys: Mutex<KeyCache>,
+}
+
+impl OidcAuth {
+    /// Gets the name of this OpenID Connect provider
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the issuer of the provider, without trailing slash
+    pub fn get_issuer(&self) -> &str {
+        self.issuer.trim_end_matches('/')
+    }
+
+    /// Gets the client ID from the config
+    pub fn get_client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Gets the secret of the OpenID Connect client from the config
+    pub fn get_secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// Gets the address to redirect to after a login attempt
+    pub fn get_redirect(&self) -> &str {
+        &self.redirect
+    }
+
+    /// Gets the API address to redirect to after a login attempt
+    pub fn get_redirect_api(&self) -> &str {
+        &self.redirect_api
+    }
+
+    /// Gets the scopes requested from the provider
+    pub fn get_scopes(&self) -> Vec<&str> {
+        self.scopes.iter().map(String::as_str).collect()
+    }
+
+    /// Gets the cache of the metadata of the provider
+    pub fn get_metadata_cache(&self) -> &Mutex<Option<ProviderMetadata>> {
+        &self.metadata
+    }
+
+    /// Gets the cache of the signing keys of the provider
+    pub fn get_keys_cache(&self) -> &Mutex<KeyCache> {
+        &self.keys
+    }
+}
+
+fn default_name() -> String {
+    "oidc".into()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".into(), "profile".into()]
+}
@@ -0,0 +1,93 @@
+use model::encrypted_token::TokenCipher;
+use model::token::TokenHasher;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Deserialize)]
+pub struct SecurityConfig {
+    /// Secret key used to hash the internal tokens of our platform
+    token_key: String,
+    /// ID of the key, in `ext_token_keys`, used to encrypt new access tokens of the providers
+    current_ext_token_key: u32,
+    /// Every key that may have encrypted an access token of a provider.
+    /// Older keys are kept here until every token has been rotated to the current one
+    ext_token_keys: Vec<ExtTokenKey>,
+    /// Addresses of the reverse proxies allowed to give the address of the client, through the
+    /// `X-Real-IP` header. Any other request is known by the address it comes from
+    #[serde(default)]
+    trusted_proxies: Vec<IpAddr>,
+}
+
+/// A key used to encrypt the access tokens of the providers
+#[derive(Deserialize)]
+pub struct ExtTokenKey {
+    /// Identifier of the key, stored alongside every token it encrypts
+    id: u32,
+    /// The key itself, as 32 base64-encoded bytes
+    key: String,
+}
+
+// The keys are left out, so that logging the configuration doesn't leak them
+impl fmt::Debug for SecurityConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecurityConfig")
+            .field("token_key", &"<redacted>")
+            .field("current_ext_token_key", &self.current_ext_token_key)
+            .field("ext_token_keys", &self.ext_token_keys)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ExtTokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExtTokenKey")
+            .field("id", &self.id)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl SecurityConfig {
+    /// Gets a hasher for the internal tokens, keyed with the configured secret
+    pub fn get_token_hasher(&self) -> TokenHasher {
+        TokenHasher::new(&self.token_key)
+    }
+
+    /// Checks whether the given address is one of a trusted reverse proxy
+    pub fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.contains(ip)
+    }
+
+    /// Gets a cipher for the access tokens of the providers, using the configured keys
+    pub fn get_token_cipher(&self) -> Result<TokenCipher, String> {
+        let keys = self
+            .ext_token_keys
+            .iter()
+            .map(|k| {
+                base64::decode(&k.key)
+                    .map(|key| (k.id, key))
+                    .map_err(|_| format!("Key {} is not valid base64", k.id))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        TokenCipher::new(self.current_ext_token_key, keys)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::SecurityConfig;
+
+    #[test]
+    pub fn redact_keys() {
+        let config: SecurityConfig = toml::from_str(
+            "token_key = \"hash_secret\"\ncurrent_ext_token_key = 1\n\
+             [[ext_token_keys]]\nid = 1\nkey = \"cipher_secret\"\n",
+        )
+        .expect("Valid security config");
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("hash_secret"));
+        assert!(!debug.contains("cipher_secret"));
+    }
+}